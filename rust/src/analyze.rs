@@ -0,0 +1,787 @@
+// Analysis pass for the stepA evaluator.
+//
+// Before a form is compiled (see compile.rs) it is turned into a tree of
+// Nodes in which every symbol has been resolved: a local to the (depth,
+// index) of its slot in the chain of fn*/let*/catch* frames, a global to
+// the var that binds it in the root env (by its name in the current
+// namespace, see ns.rs). Macros are expanded and special forms recognized
+// here, once, rather than every time the form is evaluated.
+//
+// Since macros are expanded ahead of time, a macro has to be defined
+// before code using it is analyzed. eval() runs the forms of a top-level
+// do one at a time for this reason.
+
+use types::{MalVal, MalRet, MalError, MalMap, Symbol, SrcLoc, Kind, err_syntax, err_arity};
+use types::MalError::ErrString;
+use types::{symbol, keyword, strn, list, _nil};
+use types::MalType::{Sym, Keyword, List, Vector, Hash_Map, Set, MalFunc};
+use env::{Env, Var, env_var, env_lookup, is_dynamic};
+use ns;
+
+#[derive(Clone)]
+pub enum Node {
+    Const(MalVal),
+    Local(usize, usize),
+    // the var and the name it is bound by, for the unbound error
+    Global(Var, Symbol),
+    Vector(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+    Set(Vec<Node>),
+    // def! (or defmacro! if the flag is set) to a var or a slot of the
+    // innermost frame
+    DefGlobal(Var, Symbol, Box<Node>, bool),
+    DefLocal(usize, Symbol, Box<Node>, bool),
+    // let*: the bindings as (slot, init), the frame size and the body
+    Let(Vec<(usize, Node)>, usize, Box<Node>),
+    If(Box<Node>, Box<Node>, Option<Box<Node>>),
+    Do(Vec<Node>, Box<Node>),
+    // fn*: its name, if it has one, its arities and its source form
+    Fn(Option<Symbol>, Vec<Arity>, MalVal),
+    Call(Box<Node>, Vec<Node>),
+    // binding: the dynamic vars with their names and new values, and the
+    // body to run with them bound
+    Binding(Vec<(Var, Symbol, Node)>, Box<Node>),
+    // ns (with the namespace to switch to) or require: the lib specs
+    Ns(Option<Symbol>, Vec<Node>),
+    // try*: the body, the catch* clauses and the finally forms
+    Try(Box<Node>, Vec<Catch>, Option<Box<Node>>),
+    Eval(Box<Node>),
+    // a read form with location metadata, for error reporting
+    At(MalVal, Box<Node>),
+    // the body of a loop or fn* that recurs: run again each time it ends
+    // in a recur, with the given slots rebound and then the bindings
+    // destructured from them redone
+    Loop(Vec<usize>, Vec<(usize, Node)>, Box<Node>),
+    Recur(Vec<Node>),
+    // part of the value in a slot of this frame, being destructured
+    Part(usize, Part),
+    // bind slots of this frame, then run the body: the bindings a fn*
+    // destructures from its params
+    Bind(Vec<(usize, Node)>, Box<Node>),
+    // a form that couldn't be analyzed: the error is raised if it is run
+    Fail(MalError),
+}
+
+// One arity of a fn*: the param layout (see compile::Lambda), the frame
+// size and the body
+#[derive(Clone)]
+pub struct Arity {
+    pub nfixed:   usize,
+    pub variadic: bool,
+    pub nslots:   usize,
+    pub body:     Node,
+}
+
+// Which arity a call with some number of args would run is never in doubt
+fn check_arities(arities: &[Arity]) -> Result<(), MalError> {
+    let mut variadic = None;
+    for (i, a) in arities.iter().enumerate() {
+        if a.variadic {
+            if variadic.is_some() {
+                return err_syntax("fn*: can't have more than one variadic arity");
+            }
+            variadic = Some(a.nfixed);
+        } else if arities[..i].iter().any(|b| !b.variadic && b.nfixed == a.nfixed) {
+            return err_syntax(format!("fn*: can't have two arities of {} params", a.nfixed));
+        }
+    }
+    if let Some(nfixed) = variadic {
+        if arities.iter().any(|a| !a.variadic && a.nfixed > nfixed) {
+            return err_syntax("fn*: can't have a fixed arity with more params than the variadic one");
+        }
+    }
+    Ok(())
+}
+
+// A catch* clause: what it catches (everything, without a selector), and
+// its frame size and handler
+#[derive(Clone)]
+pub struct Catch {
+    pub selector: Option<Node>,
+    pub nslots:   usize,
+    pub handler:  Node,
+}
+
+#[derive(Clone)]
+pub enum Part {
+    Nth(usize),
+    // the items from an index on, as a list
+    Rest(usize),
+    // the value as a map: a sequence of keys and values is made into one
+    Map,
+    // the value for a key of a map, or the default if it has none
+    Key(MalVal, Option<Box<Node>>),
+}
+
+fn is_pair(x: MalVal) -> bool {
+    match *x {
+        List(ref lst,_) | Vector(ref lst,_) => !lst.is_empty(),
+        _ => false,
+    }
+}
+
+pub fn quasiquote(ast: MalVal) -> MalRet {
+    if !is_pair(ast.clone()) {
+        return Ok(list(vec![symbol("quote"), ast]))
+    }
+
+    match *ast.clone() {
+        List(ref args,_) | Vector(ref args,_) => {
+            let a0 = &args[0];
+            match **a0 {
+                Sym(ref s) if *s == "unquote" => {
+                    if args.len() != 2 {
+                        return err_arity("unquote");
+                    }
+                    return Ok(args[1].clone())
+                },
+                _ => (),
+            }
+            if is_pair(a0.clone()) {
+                match **a0 {
+                    List(ref a0args,_) | Vector(ref a0args,_) => {
+                        match *a0args[0] {
+                            Sym(ref s) if *s == "splice-unquote" => {
+                                if a0args.len() != 2 {
+                                    return err_arity("splice-unquote");
+                                }
+                                return Ok(list(vec![symbol("concat"),
+                                                    a0args[1].clone(),
+                                                    quasiquote(list(args.skip(1)))?]))
+                            },
+                            _ => (),
+                        }
+                    },
+                    _ => (),
+                }
+            }
+            let rest = list(args.skip(1));
+            Ok(list(vec![symbol("cons"),
+                         quasiquote(a0.clone())?,
+                         quasiquote(rest)?]))
+        },
+        _ => Ok(_nil()), // should never reach
+    }
+}
+
+// The slots of an enclosing frame
+struct Scope {
+    // None for a slot holding a value being destructured
+    names: Vec<Option<Symbol>>,
+    // whether each slot has been bound yet. A let* binds its names in
+    // turn, but a closure in one of its inits already sees them all, as
+    // it only looks them up once it is called.
+    bound: Vec<bool>,
+    is_fn: bool,
+}
+
+impl Scope {
+    fn new(names: Vec<Option<Symbol>>, is_fn: bool) -> Scope {
+        let bound = vec![true; names.len()];
+        Scope{names, bound, is_fn}
+    }
+
+    // A new slot, unbound
+    fn add(&mut self, name: Option<Symbol>) -> usize {
+        self.names.push(name);
+        self.bound.push(false);
+        self.names.len() - 1
+    }
+
+    // The slot for name, added unbound if there isn't one
+    fn declare(&mut self, name: &Symbol) -> usize {
+        match self.names.iter().position(|n| n.as_ref() == Some(name)) {
+            Some(i) => i,
+            None => self.add(Some(name.clone())),
+        }
+    }
+}
+
+// The name a binding form binds directly: Some for a symbol, None for a
+// vector or map to destructure. ctx is the special form, for errors.
+fn binding_name(ctx: &str, pat: &MalVal) -> Result<Option<Symbol>, MalError> {
+    match **pat {
+        Sym(ref name) if name != "&" => Ok(Some(name.clone())),
+        Vector(..) | Hash_Map(..) => Ok(None),
+        _ => err_syntax(format!("{}: can't bind to {}", ctx, pat.pr_str(true))),
+    }
+}
+
+fn is_keyword(v: &MalVal, name: &str) -> bool {
+    matches!(**v, Keyword(ref k) if &**k == name)
+}
+
+// A loop or fn* that a recur can jump back to
+struct Target {
+    // the slots in its frame that recur rebinds
+    slots: Vec<usize>,
+    recurs: bool,
+}
+
+struct Analyzer {
+    root: Env,
+    // the namespace globals are resolved in, None for user
+    ns: Option<Symbol>,
+    // innermost last
+    scopes: Vec<Scope>,
+    targets: Vec<Target>,
+    // the qualified names of the globals whose def! is being analyzed, so
+    // that their values refer to them rather than to a var of user or core
+    // with the same name
+    defining: Vec<Symbol>,
+    // an error that fails the whole form as soon as it is analyzed,
+    // rather than only once the offending part is run
+    fatal: Option<MalError>,
+}
+
+/// Analyze a form to be run in the root env `root`.
+pub fn analyze(ast: &MalVal, root: &Env) -> Result<Node, MalError> {
+    let mut analyzer = Analyzer{root: root.clone(), ns: ns::current(root), scopes: vec![],
+                                targets: vec![], defining: vec![], fatal: None};
+    let node = analyzer.analyze(ast, false);
+    match analyzer.fatal {
+        Some(e) => Err(e),
+        None => Ok(*node),
+    }
+}
+
+impl Analyzer {
+    // The var a global name refers to, and its full name
+    fn global(&self, name: &Symbol) -> (Var, Symbol) {
+        let own = ns::qualify(self.ns.as_ref(), name);
+        let name = if self.defining.contains(&own) {
+            own
+        } else {
+            ns::resolve(&self.root, self.ns.as_ref(), name)
+        };
+        (env_var(&self.root, &name), name)
+    }
+
+    fn local(&self, name: &Symbol) -> Option<(usize, usize)> {
+        let mut in_fn = false;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(i) = scope.names.iter().position(|n| n.as_ref() == Some(name)) {
+                if scope.bound[i] || in_fn {
+                    return Some((depth, i));
+                }
+            }
+            in_fn |= scope.is_fn;
+        }
+        None
+    }
+
+    // The macro that a list headed by ast would call, if any
+    fn macro_fn(&self, ast: &MalVal) -> Option<MalVal> {
+        let name = match **ast {
+            Sym(ref name) => name,
+            _ => return None,
+        };
+        if self.local(name).is_some() {
+            return None;
+        }
+        let f = env_lookup(&self.root, &ns::resolve(&self.root, self.ns.as_ref(), name))?;
+        match *f {
+            MalFunc(ref mfd,_) if mfd.is_macro => Some(f.clone()),
+            _ => None,
+        }
+    }
+
+    fn macroexpand(&self, mut ast: MalVal) -> MalRet {
+        loop {
+            let expanded = match *ast {
+                List(ref args,_) if !args.is_empty() => match self.macro_fn(&args[0]) {
+                    Some(mf) => mf.apply(args.iter().skip(1).cloned().collect())?,
+                    None => break,
+                },
+                _ => break,
+            };
+            ast = expanded;
+        }
+        Ok(ast)
+    }
+
+    fn fatal<T>(&mut self, msg: String) -> Result<T, MalError> {
+        let e = ErrString(Kind::Syntax, msg);
+        if self.fatal.is_none() {
+            self.fatal = Some(e.clone());
+        }
+        Err(e)
+    }
+
+    // Analyze body as the target of the recurs in it. It runs in the frame
+    // of the innermost scope, which holds slots, and parts are the
+    // bindings destructured from them.
+    fn analyze_target(&mut self, body: &MalVal, slots: Vec<usize>,
+                      parts: Vec<(usize, Node)>) -> Box<Node> {
+        self.targets.push(Target{slots, recurs: false});
+        let body = self.analyze(body, true);
+        let target = self.targets.pop().unwrap();
+        if target.recurs {
+            Box::new(Node::Loop(target.slots, parts, body))
+        } else {
+            body
+        }
+    }
+
+    // The slot in the innermost scope that the binding form pat is bound
+    // to: the name's own for a symbol, or a hidden one for a value to
+    // destructure
+    fn bind_slot(&mut self, ctx: &str, pat: &MalVal) -> Result<usize, MalError> {
+        let name = binding_name(ctx, pat)?;
+        let scope = self.scopes.last_mut().unwrap();
+        Ok(match name {
+            Some(name) => scope.declare(&name),
+            None => scope.add(None),
+        })
+    }
+
+    // Bind pat to the value that init gives
+    fn bind_part(&mut self, ctx: &str, pat: &MalVal, init: Node,
+                 inits: &mut Vec<(usize, Node)>) -> Result<(), MalError> {
+        let slot = self.bind_slot(ctx, pat)?;
+        inits.push((slot, init));
+        self.destructure(ctx, pat, slot, inits)
+    }
+
+    // Bind the names in pat to the parts of the value in slot from, adding
+    // the inits that do so to inits
+    fn destructure(&mut self, ctx: &str, pat: &MalVal, from: usize,
+                   inits: &mut Vec<(usize, Node)>) -> Result<(), MalError> {
+        self.scopes.last_mut().unwrap().bound[from] = true;
+        match **pat {
+            Vector(ref items,_) => {
+                let mut i = 0;
+                while i < items.len() {
+                    if is_keyword(&items[i], "as") {
+                        match items.get(i + 1) {
+                            Some(name) if i + 2 == items.len() && matches!(**name, Sym(_)) => {
+                                return self.bind_part(ctx, name, Node::Local(0, from), inits);
+                            },
+                            _ => return err_syntax(format!(
+                                "{}: :as must be followed by a symbol, at the end", ctx)),
+                        }
+                    }
+                    if matches!(*items[i], Sym(ref s) if s == "&") {
+                        let rest = match items.get(i + 1) {
+                            Some(rest) if !is_keyword(rest, "as") => rest,
+                            _ => return err_syntax(format!(
+                                "{}: & must be followed by a binding form", ctx)),
+                        };
+                        self.bind_part(ctx, rest, Node::Part(from, Part::Rest(i)), inits)?;
+                        if i + 2 < items.len() && !is_keyword(&items[i + 2], "as") {
+                            return err_syntax(format!(
+                                "{}: only :as can follow the & binding", ctx));
+                        }
+                        i += 2;
+                        continue;
+                    }
+                    self.bind_part(ctx, &items[i], Node::Part(from, Part::Nth(i)), inits)?;
+                    i += 1;
+                }
+            },
+            Hash_Map(ref hm,_) => {
+                inits.push((from, Node::Part(from, Part::Map)));
+                let defaults = match hm.get(&keyword("or")) {
+                    Some(or) => match **or {
+                        Hash_Map(ref defaults,_) => defaults.clone(),
+                        _ => return err_syntax(format!("{}: :or must be a map", ctx)),
+                    },
+                    None => MalMap::new(),
+                };
+                if let Some(name) = hm.get(&keyword("as")) {
+                    if !matches!(**name, Sym(_)) {
+                        return err_syntax(format!("{}: :as must be followed by a symbol", ctx));
+                    }
+                    self.bind_part(ctx, name, Node::Local(0, from), inits)?;
+                }
+                for (k, v) in hm.iter() {
+                    let kind = match **k {
+                        Keyword(ref kind) => &**kind,
+                        _ => {
+                            self.bind_key(ctx, k, from, v.clone(), &defaults, inits)?;
+                            continue;
+                        },
+                    };
+                    if kind != "keys" && kind != "strs" && kind != "syms" {
+                        if kind != "as" && kind != "or" {
+                            return err_syntax(format!("{}: can't bind to :{}", ctx, kind));
+                        }
+                        continue;
+                    }
+                    let names = match **v {
+                        Vector(ref names,_) if names.iter().all(|n| matches!(**n, Sym(_))) => names,
+                        _ => return err_syntax(format!(
+                            "{}: :{} must be a vector of symbols", ctx, kind)),
+                    };
+                    for name in names.iter() {
+                        let key = match kind {
+                            "keys" => keyword(&name.pr_str(true)),
+                            "strs" => strn(&name.pr_str(true)),
+                            _ => name.clone(),
+                        };
+                        self.bind_key(ctx, name, from, key, &defaults, inits)?;
+                    }
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // The param layout, frame size and body of an arity of a fn*
+    fn analyze_arity(&mut self, params: &MalVal, body: &MalVal) -> Result<Arity, MalError> {
+        let mut params: Vec<MalVal> = match **params {
+            List(ref ps,_) | Vector(ref ps,_) => ps.iter().cloned().collect(),
+            _ => return err_syntax("fn* with non-list parameters"),
+        };
+        let nfixed = params.iter()
+            .position(|p| matches!(**p, Sym(ref s) if s == "&"))
+            .unwrap_or(params.len());
+        let variadic = nfixed < params.len();
+        if variadic {
+            match params.len() - nfixed {
+                1 => return err_syntax("& bind without a symbol"),
+                2 => { params.remove(nfixed); },
+                _ => return err_syntax("fn*: & must be followed by a single binding form"),
+            }
+        }
+        let names = params.iter().map(|p| binding_name("fn*", p))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.scopes.push(Scope::new(names, true));
+        let mut parts = vec![];
+        for (i, p) in params.iter().enumerate() {
+            if self.scopes.last().unwrap().names[i].is_none() {
+                self.destructure("fn*", p, i, &mut parts)?;
+            }
+        }
+        let body = self.analyze_target(body, (0..params.len()).collect(), parts.clone());
+        let body = if parts.is_empty() {
+            *body
+        } else {
+            Node::Bind(parts, body)
+        };
+        let nslots = self.scopes.pop().unwrap().names.len();
+        Ok(Arity{nfixed, variadic, nslots, body})
+    }
+
+    // Bind pat to the value for key in the map in slot from, or to its
+    // default in defaults if there is none
+    fn bind_key(&mut self, ctx: &str, pat: &MalVal, from: usize, key: MalVal,
+                defaults: &MalMap, inits: &mut Vec<(usize, Node)>) -> Result<(), MalError> {
+        let default = defaults.get(pat).map(|d| self.analyze(d, false));
+        self.bind_part(ctx, pat, Node::Part(from, Part::Key(key, default)), inits)
+    }
+
+    // tail is whether ast is in tail position of the innermost target
+    fn analyze(&mut self, ast: &MalVal, tail: bool) -> Box<Node> {
+        Box::new(match **ast {
+            Sym(ref name) => match self.local(name) {
+                Some((depth, i)) => Node::Local(depth, i),
+                None => {
+                    let (var, name) = self.global(name);
+                    Node::Global(var, name)
+                },
+            },
+            List(ref args, ref meta) if !args.is_empty() => {
+                let nscopes = self.scopes.len();
+                let ntargets = self.targets.len();
+                let node = match self.analyze_list(ast, tail) {
+                    Ok(node) => node,
+                    Err(e) => {
+                        self.scopes.truncate(nscopes);
+                        self.targets.truncate(ntargets);
+                        if let Some(loc) = SrcLoc::of(ast) {
+                            self.fatal = self.fatal.take().map(|f| f.at(loc));
+                        }
+                        Box::new(Node::Fail(e))
+                    },
+                };
+                match **meta {
+                    Hash_Map(_,_) => Node::At(ast.clone(), node),
+                    _ => return node,
+                }
+            },
+            Vector(ref items,_) => {
+                Node::Vector(items.iter().map(|i| *self.analyze(i, false)).collect())
+            },
+            Hash_Map(ref hm,_) => {
+                Node::Map(hm.iter().map(|(k, v)| {
+                    (*self.analyze(k, false), *self.analyze(v, false))
+                }).collect())
+            },
+            Set(ref items,_) => {
+                Node::Set(items.iter().map(|i| *self.analyze(i, false)).collect())
+            },
+            _ => Node::Const(ast.clone()),
+        })
+    }
+
+    fn analyze_list(&mut self, ast: &MalVal, tail: bool) -> Result<Box<Node>, MalError> {
+        let args = match **ast {
+            List(ref args,_) => args,
+            _ => return err_syntax("Expected list"),
+        };
+        if let Some(mf) = self.macro_fn(&args[0]) {
+            let expanded = mf.apply(args.iter().skip(1).cloned().collect())?;
+            return Ok(self.analyze(&expanded, tail));
+        }
+        let a0sym = match *args[0] {
+            Sym(ref a0sym) => &a0sym[..],
+            _ => "__<fn*>__",
+        };
+
+        Ok(Box::new(match a0sym {
+            "def!" | "defmacro!" => {
+                if args.len() != 3 {
+                    return err_syntax(format!("Wrong arity to {} call", a0sym));
+                }
+                let name = match *args[1] {
+                    Sym(ref name) => name.clone(),
+                    _ => return err_syntax(format!("{} of non-symbol", a0sym)),
+                };
+                let is_macro = a0sym == "defmacro!";
+                // the name is bound before the value is analyzed, so that a
+                // function can refer to itself
+                let slot = self.scopes.last_mut().map(|scope| {
+                    let i = scope.declare(&name);
+                    scope.bound[i] = true;
+                    i
+                });
+                match slot {
+                    Some(i) => Node::DefLocal(i, name, self.analyze(&args[2], false), is_macro),
+                    None => {
+                        let name = ns::qualify(self.ns.as_ref(), &name);
+                        self.defining.push(name.clone());
+                        let val = self.analyze(&args[2], false);
+                        self.defining.pop();
+                        Node::DefGlobal(env_var(&self.root, &name), name, val, is_macro)
+                    },
+                }
+            },
+            "let*" | "loop" => {
+                if args.len() != 3 {
+                    return err_syntax(format!("Wrong arity to {} call", a0sym));
+                }
+                let binds = match *args[1] {
+                    List(ref binds,_) | Vector(ref binds,_) => binds,
+                    _ => return err_syntax(format!("{} with non-list bindings", a0sym)),
+                };
+                if binds.len() % 2 == 1 {
+                    return err_syntax(format!("{} with odd number of binding forms", a0sym));
+                }
+                self.scopes.push(Scope::new(vec![], false));
+                let mut slots = vec![];
+                for pat in binds.iter().step_by(2) {
+                    slots.push(self.bind_slot(a0sym, pat)?);
+                }
+                let mut inits = vec![];
+                // the inits destructuring the bound values, which a loop
+                // redoes on each recur
+                let mut parts = vec![];
+                let pats = binds.iter().step_by(2);
+                for ((&i, pat), exp) in slots.iter().zip(pats).zip(binds.iter().skip(1).step_by(2)) {
+                    inits.push((i, *self.analyze(exp, false)));
+                    let start = inits.len();
+                    self.destructure(a0sym, pat, i, &mut inits)?;
+                    parts.extend_from_slice(&inits[start..]);
+                }
+                let body = if a0sym == "loop" {
+                    self.analyze_target(&args[2], slots, parts)
+                } else {
+                    self.analyze(&args[2], tail)
+                };
+                let nslots = self.scopes.pop().unwrap().names.len();
+                Node::Let(inits, nslots, body)
+            },
+            "binding" => {
+                if args.len() != 3 {
+                    return err_arity("binding");
+                }
+                let binds = match *args[1] {
+                    List(ref binds,_) | Vector(ref binds,_) => binds,
+                    _ => return err_syntax("binding with non-list bindings"),
+                };
+                if binds.len() % 2 == 1 {
+                    return err_syntax("binding with odd number of binding forms");
+                }
+                let mut vars = vec![];
+                for (b, exp) in binds.iter().step_by(2).zip(binds.iter().skip(1).step_by(2)) {
+                    let name = match **b {
+                        Sym(ref name) if is_dynamic(name) => name.clone(),
+                        _ => return err_syntax(format!("binding: {} is not a dynamic var",
+                                                       b.pr_str(true))),
+                    };
+                    let init = *self.analyze(exp, false);
+                    let (var, name) = self.global(&name);
+                    vars.push((var, name, init));
+                }
+                // not in tail position: the bindings are popped once it is done
+                Node::Binding(vars, self.analyze(&args[2], false))
+            },
+            "recur" => {
+                let nslots = match self.targets.last() {
+                    Some(target) => target.slots.len(),
+                    None => return self.fatal("recur outside of loop or fn*".to_string()),
+                };
+                if !tail {
+                    return self.fatal("recur not in tail position".to_string());
+                }
+                if args.len() - 1 != nslots {
+                    return self.fatal(format!("recur: expected {} arguments, got {}",
+                                              nslots, args.len() - 1));
+                }
+                self.targets.last_mut().unwrap().recurs = true;
+                Node::Recur(args.iter().skip(1).map(|a| *self.analyze(a, false)).collect())
+            },
+            "quote" => {
+                if args.len() != 2 {
+                    return err_arity("quote");
+                }
+                Node::Const(args[1].clone())
+            },
+            "quasiquote" => {
+                if args.len() != 2 {
+                    return err_arity("quasiquote");
+                }
+                return Ok(self.analyze(&quasiquote(args[1].clone())?, tail));
+            },
+            "macroexpand" => {
+                if args.len() != 2 {
+                    return err_arity("macroexpand");
+                }
+                Node::Const(self.macroexpand(args[1].clone())?)
+            },
+            "try*" => {
+                if args.len() < 2 {
+                    return err_arity("try*");
+                }
+                let body = self.analyze(&args[1], false);
+                let mut catches = vec![];
+                let mut finally = None;
+                for (i, clause) in args.iter().enumerate().skip(2) {
+                    let clause = match **clause {
+                        List(ref clause,_) => clause,
+                        _ => return err_syntax("invalid catch* clause"),
+                    };
+                    match clause.front().map(|c| &**c) {
+                        Some(Sym(c0)) if c0 == "catch*" => {},
+                        Some(Sym(c0)) if c0 == "finally" => {
+                            if i != args.len() - 1 {
+                                return err_syntax("finally must be the last clause of try*");
+                            }
+                            let forms = clause.iter().skip(1)
+                                .map(|form| *self.analyze(form, false)).collect();
+                            finally = Some(Box::new(Node::Do(forms, Box::new(Node::Const(_nil())))));
+                            continue;
+                        },
+                        _ => return err_syntax("invalid catch* clause"),
+                    }
+                    // (catch* name handler) or (catch* selector name handler)
+                    let (selector, name, handler) = match clause.len() {
+                        3 => (None, &clause[1], &clause[2]),
+                        4 => (Some(*self.analyze(&clause[1], false)), &clause[2], &clause[3]),
+                        _ => return err_syntax("wrong arity to catch* clause"),
+                    };
+                    let name = match **name {
+                        Sym(ref name) => name.clone(),
+                        _ => return err_syntax("invalid catch* binding"),
+                    };
+                    self.scopes.push(Scope::new(vec![Some(name), Some(Symbol::new("*backtrace*"))], false));
+                    let handler = *self.analyze(handler, false);
+                    let nslots = self.scopes.pop().unwrap().names.len();
+                    catches.push(Catch{selector, nslots, handler});
+                }
+                Node::Try(body, catches, finally)
+            },
+            "do" => {
+                if args.len() == 1 {
+                    return Ok(Box::new(Node::Const(_nil())));
+                }
+                let forms: Vec<Node> = args.iter().skip(1).take(args.len() - 2)
+                    .map(|form| *self.analyze(form, false)).collect();
+                let last = self.analyze(&args[args.len() - 1], tail);
+                Node::Do(forms, last)
+            },
+            "if" => {
+                if args.len() != 3 && args.len() != 4 {
+                    return err_arity("if");
+                }
+                let cond = self.analyze(&args[1], false);
+                let then = self.analyze(&args[2], tail);
+                let els = args.get(3).map(|a| self.analyze(a, tail));
+                Node::If(cond, then, els)
+            },
+            "fn*" => {
+                let name = match args.get(1).map(|a| &**a) {
+                    Some(Sym(ref name)) => Some(name.clone()),
+                    _ => None,
+                };
+                let clauses = args.skip(if name.is_some() { 2 } else { 1 });
+                // (fn* name? params body) or (fn* name? (params body)...)
+                let multi = !clauses.is_empty() && clauses.iter().all(|c| match **c {
+                    List(ref c,_) => matches!(c.front().map(|p| &**p), Some(List(..)) | Some(Vector(..))),
+                    _ => false,
+                });
+                if !multi && clauses.len() != 2 {
+                    return err_arity("fn*");
+                }
+                if let Some(ref name) = name {
+                    self.scopes.push(Scope::new(vec![Some(name.clone())], false));
+                }
+                let mut arities = vec![];
+                if multi {
+                    for c in clauses.iter() {
+                        match **c {
+                            List(ref c,_) if c.len() == 2 => {
+                                arities.push(self.analyze_arity(&c[0], &c[1])?);
+                            },
+                            _ => return err_syntax("fn*: each arity must be a (params body) list"),
+                        }
+                    }
+                } else {
+                    arities.push(self.analyze_arity(&clauses[0], &clauses[1])?);
+                }
+                if name.is_some() {
+                    self.scopes.pop();
+                }
+                check_arities(&arities)?;
+                Node::Fn(name, arities, ast.clone())
+            },
+            "ns" => {
+                let name = match args.get(1).map(|n| &**n) {
+                    Some(Sym(ref name)) => name.clone(),
+                    _ => return err_syntax("ns of non-symbol"),
+                };
+                // (ns name (:require spec...)...), the specs unevaluated
+                let mut specs = vec![];
+                for clause in args.iter().skip(2) {
+                    match **clause {
+                        List(ref c,_) if c.front().is_some_and(|k| is_keyword(k, "require")) => {
+                            specs.extend(c.iter().skip(1).map(|s| Node::Const(s.clone())));
+                        },
+                        _ => return err_syntax(format!("ns: unsupported clause {}",
+                                                       clause.pr_str(true))),
+                    }
+                }
+                Node::Ns(Some(name), specs)
+            },
+            "require" => {
+                if args.len() < 2 {
+                    return err_arity("require");
+                }
+                Node::Ns(None, args.iter().skip(1).map(|s| *self.analyze(s, false)).collect())
+            },
+            "eval" => {
+                if args.len() != 2 {
+                    return err_arity("eval");
+                }
+                Node::Eval(self.analyze(&args[1], false))
+            },
+            _ => { // function call
+                let f = self.analyze(&args[0], false);
+                let fargs = args.iter().skip(1).map(|a| *self.analyze(a, false)).collect();
+                Node::Call(f, fargs)
+            },
+        }))
+    }
+}
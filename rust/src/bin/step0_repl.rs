@@ -20,7 +20,7 @@ fn print(exp: String) -> String {
 fn main() {
     loop {
         let line = readline::mal_readline("user> ");
-        match line { None => break, _ => () }
+        if line.is_none() { break }
         println!("{}", print(eval(read(line.unwrap()))));
     }
 }
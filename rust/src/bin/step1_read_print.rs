@@ -1,7 +1,7 @@
 extern crate mal;
 
 use mal::types::{MalVal, MalRet, MalError};
-use mal::types::MalError::{ErrString, ErrMalVal};
+use mal::types::MalError::ErrMalVal;
 use mal::{readline, reader};
 
 // read
@@ -20,20 +20,20 @@ fn print(exp: MalVal) -> String {
 }
 
 fn rep(str: &str) -> Result<String,MalError> {
-    let ast = try!(read(str.to_string()));
+    let ast = read(str.to_string())?;
     //println!("read: {}", ast);
-    let exp = try!(eval(ast));
+    let exp = eval(ast)?;
     Ok(print(exp))
 }
 
 fn main() {
     loop {
         let line = readline::mal_readline("user> ");
-        match line { None => break, _ => () }
+        if line.is_none() { break }
         match rep(&line.unwrap()) {
             Ok(str)  => println!("{}", str),
             Err(ErrMalVal(_)) => (),  // Blank line
-            Err(ErrString(s)) => println!("Error: {}", s),
+            Err(e) => println!("Error: {}", e),
         }
     }
 }
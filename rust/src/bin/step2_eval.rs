@@ -2,9 +2,9 @@ extern crate mal;
 
 use std::collections::HashMap;
 
-use mal::types::{MalVal, MalRet, MalError, err_str, err_string};
+use mal::types::{MalVal, MalMap, MalRet, MalError, err_str, err_string};
 use mal::types::{list, vector, hash_map, _int, func};
-use mal::types::MalError::{ErrString, ErrMalVal};
+use mal::types::MalError::ErrMalVal;
 use mal::types::MalType::{Sym, List, Vector, Hash_Map, Int};
 use mal::{readline, reader};
 
@@ -17,7 +17,7 @@ fn read(str: String) -> MalRet {
 fn eval_ast(ast: MalVal, env: &HashMap<String,MalVal>) -> MalRet {
     match *ast {
         Sym(ref sym) => {
-            match env.get(sym) {
+            match env.get(sym.name()) {
                 Some(mv) => Ok(mv.clone()),
                 //None     => Ok(_nil()),
                 None     => err_string(format!("'{}' not found", sym)),
@@ -27,16 +27,16 @@ fn eval_ast(ast: MalVal, env: &HashMap<String,MalVal>) -> MalRet {
             let mut ast_vec : Vec<MalVal> = vec![];
             for mv in a.iter() {
                 let mv2 = mv.clone();
-                ast_vec.push(try!(eval(mv2, env)));
+                ast_vec.push(eval(mv2, env)?);
             }
             Ok(match *ast { List(_,_) => list(ast_vec),
                             _         => vector(ast_vec) })
         }
         Hash_Map(ref hm,_) => {
-            let mut new_hm: HashMap<String,MalVal> = HashMap::new();
+            let mut new_hm = MalMap::new();
             for (key, value) in hm.iter() {
-                new_hm.insert(key.to_string(),
-                              try!(eval(value.clone(), env)));
+                new_hm.insert(key.clone(),
+                              eval(value.clone(), env)?);
             }
             Ok(hash_map(new_hm))
         }
@@ -52,18 +52,18 @@ fn eval(ast: MalVal, env: &HashMap<String,MalVal>) -> MalRet {
     }
 
     // apply list
-    match *try!(eval_ast(ast, env)) {
+    match *eval_ast(ast, env)? {
         List(ref args,_) => {
             match args.len() {
                 0 =>
                     Ok(list(vec![])),
                 _ =>  {
-                    let ref f = args.clone()[0];
-                    f.apply(args[1..].to_vec())
+                    let f = &args.clone()[0];
+                    f.apply(args.iter().skip(1).cloned().collect())
                 }
             }
         },
-        _ => return err_str("Expected list"),
+        _ => err_str("Expected list"),
     }
 }
 
@@ -73,9 +73,9 @@ fn print(exp: MalVal) -> String {
 }
 
 fn rep(str: &str, env: &HashMap<String,MalVal>) -> Result<String,MalError> {
-    let ast = try!(read(str.to_string()));
+    let ast = read(str.to_string())?;
     //println!("read: {}", ast);
-    let exp = try!(eval(ast, env));
+    let exp = eval(ast, env)?;
     Ok(print(exp))
 }
 
@@ -104,11 +104,11 @@ fn main() {
 
     loop {
         let line = readline::mal_readline("user> ");
-        match line { None => break, _ => () }
+        if line.is_none() { break }
         match rep(&line.unwrap(), &repl_env) {
             Ok(str)  => println!("{}", str),
             Err(ErrMalVal(_)) => (),  // Blank line
-            Err(ErrString(s)) => println!("Error: {}", s),
+            Err(e) => println!("Error: {}", e),
         }
     }
 }
@@ -1,10 +1,9 @@
 extern crate mal;
 
-use std::collections::HashMap;
 
-use mal::types::{MalVal, MalRet, MalError, err_str};
+use mal::types::{MalVal, MalMap, MalRet, MalError, err_str};
 use mal::types::{symbol, _nil, list, vector, hash_map, malfunc};
-use mal::types::MalError::{ErrString, ErrMalVal};
+use mal::types::MalError::ErrMalVal;
 use mal::types::MalType::{Nil, False, Sym, List, Vector, Hash_Map};
 use mal::{readline, reader, core};
 use mal::env::{env_set, env_get, env_new, Env};
@@ -23,16 +22,16 @@ fn eval_ast(ast: MalVal, env: Env) -> MalRet {
             let mut ast_vec : Vec<MalVal> = vec![];
             for mv in a.iter() {
                 let mv2 = mv.clone();
-                ast_vec.push(try!(eval(mv2, env.clone())));
+                ast_vec.push(eval(mv2, env.clone())?);
             }
             Ok(match *ast { List(_,_) => list(ast_vec),
                             _         => vector(ast_vec) })
         }
         Hash_Map(ref hm,_) => {
-            let mut new_hm: HashMap<String,MalVal> = HashMap::new();
+            let mut new_hm = MalMap::new();
             for (key, value) in hm.iter() {
-                new_hm.insert(key.to_string(),
-                              try!(eval(value.clone(), env.clone())));
+                new_hm.insert(key.clone(),
+                              eval(value.clone(), env.clone())?);
             }
             Ok(hash_map(new_hm))
         }
@@ -57,10 +56,10 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
     let tmp = ast;
     let (args, a0sym) = match *tmp {
         List(ref args,_) => {
-            if args.len() == 0 {
+            if args.is_empty() {
                 return Ok(tmp.clone());
             }
-            let ref a0 = *args[0];
+            let a0 = &*args[0];
             match *a0 {
                 Sym(ref a0sym) => (args, &a0sym[..]),
                 _ => (args, "__<fn*>__"),
@@ -73,13 +72,13 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
         "def!" => {
             let a1 = (*args)[1].clone();
             let a2 = (*args)[2].clone();
-            let r = try!(eval(a2, env.clone()));
+            let r = eval(a2, env.clone())?;
             match *a1 {
                 Sym(_) => {
                     env_set(&env.clone(), a1, r.clone());
-                    return Ok(r);
+                    Ok(r)
                 },
-                _ => return err_str("def! of non-symbol"),
+                _ => err_str("def! of non-symbol"),
             }
         },
         "let*" => {
@@ -94,7 +93,7 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                         let exp = it.next().unwrap();
                         match **b {
                             Sym(_) => {
-                                let r = try!(eval(exp.clone(), let_env.clone()));
+                                let r = eval(exp.clone(), let_env.clone())?;
                                 env_set(&let_env, b.clone(), r);
                             },
                             _ => return err_str("let* with non-symbol binding"),
@@ -103,49 +102,49 @@ fn eval(ast: MalVal, env: Env) -> MalRet {
                 },
                 _ => return err_str("let* with non-list bindings"),
             }
-            return eval(a2, let_env.clone());
+            eval(a2, let_env.clone())
         },
         "do" => {
-            let el = list(args[1..].to_vec());
-            match *try!(eval_ast(el, env.clone())) {
+            let el = list(args.skip(1));
+            match *eval_ast(el, env.clone())? {
                 List(ref lst,_) => {
-                    let ref last = lst[lst.len()-1];
-                    return Ok(last.clone());
+                    let last = &lst[lst.len()-1];
+                    Ok(last.clone())
                 }
-                _ => return err_str("invalid do call"),
+                _ => err_str("invalid do call"),
             }
         },
         "if" => {
             let a1 = (*args)[1].clone();
-            let c = try!(eval(a1, env.clone()));
+            let c = eval(a1, env.clone())?;
             match *c {
                 False | Nil => {
                     if args.len() >= 4 {
                         let a3 = (*args)[3].clone();
-                        return eval(a3, env.clone());
+                        eval(a3, env.clone())
                     } else {
-                        return Ok(_nil());
+                        Ok(_nil())
                     }
                 },
                 _ => {
                     let a2 = (*args)[2].clone();
-                    return eval(a2, env.clone());
+                    eval(a2, env.clone())
                 },
             }
         },
         "fn*" => {
             let a1 = args[1].clone();
             let a2 = args[2].clone();
-            return Ok(malfunc(eval, a2, env, a1, _nil()));
+            Ok(malfunc(eval, a2, env, a1, _nil()))
         },
         _ => { // function call
-            let el = try!(eval_ast(tmp.clone(), env.clone()));
+            let el = eval_ast(tmp.clone(), env.clone())?;
             let args = match *el {
                 List(ref args,_) => args,
                 _ => return err_str("Invalid apply"),
             };
-            let ref f = args.clone()[0];
-            f.apply(args[1..].to_vec())
+            let f = &args.clone()[0];
+            f.apply(args.iter().skip(1).cloned().collect())
         },
     }
 }
@@ -156,9 +155,9 @@ fn print(exp: MalVal) -> String {
 }
 
 fn rep(str: &str, env: Env) -> Result<String,MalError> {
-    let ast = try!(read(str.to_string()));
+    let ast = read(str.to_string())?;
     //println!("read: {}", ast);
-    let exp = try!(eval(ast, env));
+    let exp = eval(ast, env)?;
     Ok(print(exp))
 }
 
@@ -175,11 +174,11 @@ fn main() {
     // repl loop
     loop {
         let line = readline::mal_readline("user> ");
-        match line { None => break, _ => () }
+        if line.is_none() { break }
         match rep(&line.unwrap(), repl_env.clone()) {
             Ok(str)  => println!("{}", str),
             Err(ErrMalVal(_)) => (),  // Blank line
-            Err(ErrString(s)) => println!("Error: {}", s),
+            Err(e) => println!("Error: {}", e),
         }
     }
 }
@@ -0,0 +1,503 @@
+// Compiles analyzed forms (see analyze.rs) into a tree of closures.
+//
+// Each closure evaluates one node given the frame it runs in. A form in
+// tail position is run by calling its Code directly; only a call to a mal
+// function or an eval returns a Step::Eval, which eval_code() loops on,
+// so tail calls don't grow the Rust stack. Forms in any other position
+// are compiled to an Operand.
+
+use std::rc::Rc;
+
+use types::{MalVal, MalRet, MalError, MalMap, MalSet, MalFuncData, Symbol, Kind};
+use types::{err_kind, err_type, arity_error};
+use types::{_nil, symbol, list, vector, hash_map, hash_mapv, set, malfuncd};
+use types::MalType::{Nil, False, Keyword, List, Vector, Hash_Map, Func, NativeFunc, MalFunc};
+use env::{Env, Var, env_frame, env_slot, env_set, env_set_slot, env_recur, env_root,
+          var_get, var_bind, var_unbind};
+use analyze::{analyze, Node, Part};
+use eval::{eval, eval_code, is_toplevel_do, Position};
+use ns;
+
+pub enum Step {
+    Value(MalVal),
+    // continue by running the code in the env (a tail call)
+    Eval(Code, Env),
+    // run the innermost loop or fn* body again with these values
+    Recur(Vec<MalVal>),
+}
+
+pub type Code = Rc<dyn Fn(&Env, &mut Position) -> Result<Step, MalError>>;
+
+// A form in non-tail position, which just returns its value. Constants
+// and variables are looked at directly rather than through a closure.
+pub enum Operand {
+    Const(MalVal),
+    Local(usize, usize),
+    Global(Var, Symbol),
+    Code(Code),
+}
+
+impl Operand {
+    #[inline]
+    pub fn eval(&self, env: &Env) -> MalRet {
+        match *self {
+            Operand::Const(ref v) => Ok(v.clone()),
+            Operand::Local(depth, i) => Ok(env_slot(env, depth, i)),
+            Operand::Global(ref var, ref name) => var_value(var, name),
+            Operand::Code(ref code) => eval_code(code, env),
+        }
+    }
+}
+
+// One arity of a function
+pub struct Lambda {
+    // the required params; a rest param takes the slot after them
+    pub nfixed:   usize,
+    pub variadic: bool,
+    // the params plus the locals def!'d in the body
+    pub nslots:   usize,
+    pub body:     Code,
+}
+
+// Give an unnamed mal function the name it is being def!'d to
+fn named(f: MalVal, name: &str) -> MalVal {
+    match *f {
+        MalFunc(ref mfd, ref meta) if mfd.name.is_none() => {
+            let mut new_mfd = mfd.clone();
+            new_mfd.name = Some(name.to_string());
+            malfuncd(new_mfd, meta.clone())
+        },
+        _ => f.clone(),
+    }
+}
+
+// The value def! or defmacro! binds name to
+fn defined(val: MalVal, name: &str, is_macro: bool) -> MalRet {
+    if !is_macro {
+        return Ok(named(val, name));
+    }
+    match *val {
+        MalFunc(ref mfd,_) => {
+            let mut new_mfd = mfd.clone();
+            new_mfd.is_macro = true;
+            if new_mfd.name.is_none() {
+                new_mfd.name = Some(name.to_string());
+            }
+            Ok(malfuncd(new_mfd,_nil()))
+        },
+        _ => err_type("defmacro! of non-function"),
+    }
+}
+
+fn var_value(var: &Var, name: &Symbol) -> MalRet {
+    match var_get(var, name) {
+        Some(v) => Ok(v),
+        None => err_kind(Kind::NotFound, format!("'{}' not found", name)),
+    }
+}
+
+// The arity of the named function that a call with nargs args runs
+fn select_arity<'a>(lambdas: &'a [Lambda], name: Option<&str>, nargs: usize)
+                    -> Result<&'a Lambda, MalError> {
+    let takes = |l: &&Lambda| if l.variadic { nargs >= l.nfixed } else { nargs == l.nfixed };
+    // a fixed arity is picked over a variadic one that also takes nargs
+    match lambdas.iter().filter(|l| !l.variadic).find(takes) {
+        Some(lambda) => Ok(lambda),
+        None => lambdas.iter().find(takes).ok_or_else(|| {
+            let arities: Vec<(usize, bool)> = lambdas.iter()
+                .map(|l| (l.nfixed, l.variadic)).collect();
+            arity_error(name, &arities, nargs)
+        }),
+    }
+}
+
+// The arity of the named function that a call with args runs, and a new
+// frame for it with its params bound to args
+pub fn bind_args<'a>(lambdas: &'a [Lambda], name: Option<&str>, env: &Env,
+                     mut args: Vec<MalVal>) -> Result<(&'a Lambda, Env), MalError> {
+    let lambda = select_arity(lambdas, name, args.len())?;
+    if lambda.variadic {
+        let rest = args.split_off(lambda.nfixed);
+        args.push(list(rest));
+    }
+    args.resize(lambda.nslots, _nil());
+    Ok((lambda, env_frame(Some(env.clone()), args)))
+}
+
+fn not_a(what: &str, v: &MalVal) -> MalRet {
+    err_type(format!("can't destructure {} as a {}", v.pr_str(true), what))
+}
+
+fn nth_part(v: &MalVal, i: usize) -> MalRet {
+    match **v {
+        List(ref items,_) | Vector(ref items,_) => Ok(items.get(i).cloned().unwrap_or_else(_nil)),
+        Nil => Ok(_nil()),
+        _ => not_a("sequence", v),
+    }
+}
+
+fn rest_part(v: &MalVal, i: usize) -> MalRet {
+    match **v {
+        List(ref items,_) | Vector(ref items,_) if i < items.len() => Ok(list(items.skip(i))),
+        List(..) | Vector(..) | Nil => Ok(list(vec![])),
+        _ => not_a("sequence", v),
+    }
+}
+
+fn map_part(v: &MalVal) -> MalRet {
+    match **v {
+        Hash_Map(..) | Nil => Ok(v.clone()),
+        List(ref items,_) | Vector(ref items,_) => hash_mapv(items.iter().cloned().collect()),
+        _ => not_a("map", v),
+    }
+}
+
+// Set slots of env to the values of inits, in turn
+fn bind_all(inits: &[(usize, Operand)], env: &Env) -> Result<(), MalError> {
+    for (i, init) in inits.iter() {
+        let r = init.eval(env)?;
+        env_set_slot(env, *i, r);
+    }
+    Ok(())
+}
+
+fn compile_inits(inits: Vec<(usize, Node)>) -> Vec<(usize, Operand)> {
+    inits.into_iter().map(|(i, init)| (i, operand(init))).collect()
+}
+
+struct CatchCode {
+    selector: Option<Operand>,
+    nslots:   usize,
+    handler:  Operand,
+}
+
+// Whether a catch* clause whose selector is sel catches err, which it
+// binds as exc: sel is either a keyword naming the type of exc or the kind
+// of err (see MalError::kind), or a predicate on exc
+fn selects(sel: &MalVal, err: &MalError, exc: &MalVal) -> Result<bool, MalError> {
+    match **sel {
+        Keyword(ref name) => Ok(&**name == exc.type_name() || err.kind().as_ref() == Some(sel)),
+        Func(..) | NativeFunc(..) | MalFunc(..) => {
+            Ok(!matches!(*sel.apply(vec![exc.clone()])?, Nil | False))
+        },
+        _ => err_type(format!("catch* selector must be a keyword or a function, got {}",
+                              sel.pr_str(true))),
+    }
+}
+
+// Run the handler of the first of catches that catches err, or else
+// give err back
+fn run_catch(catches: &[CatchCode], env: &Env, err: MalError) -> MalRet {
+    let exc = err.clone().exc_value();
+    for c in catches.iter() {
+        if let Some(ref sel) = c.selector {
+            if !selects(&sel.eval(env)?, &err, &exc)? {
+                continue;
+            }
+        }
+        let bt = match err.backtrace() {
+            Some(bt) => bt.to_mal(),
+            None => vector(vec![]),
+        };
+        let mut slots = vec![exc, bt];
+        slots.resize(c.nslots, _nil());
+        return c.handler.eval(&env_frame(Some(env.clone()), slots));
+    }
+    Err(err)
+}
+
+fn value(v: MalVal) -> Result<Step, MalError> {
+    Ok(Step::Value(v))
+}
+
+fn compile_all(nodes: Vec<Node>) -> Vec<Operand> {
+    nodes.into_iter().map(operand).collect()
+}
+
+fn operand(node: Node) -> Operand {
+    match node {
+        Node::Const(v) => Operand::Const(v),
+        Node::Local(depth, i) => Operand::Local(depth, i),
+        Node::Global(var, name) => Operand::Global(var, name),
+        node => Operand::Code(compile(node)),
+    }
+}
+
+// A call, and the read form it came from if that has a location
+fn compile_call(f: Node, args: Vec<Node>, form: Option<MalVal>) -> Code {
+    let fcode = operand(f);
+    let args = compile_all(args);
+    Rc::new(move |env, pos| {
+        if form.is_some() {
+            pos.form = form.clone();
+        }
+        let f = fcode.eval(env)?;
+        let mut fargs = Vec::with_capacity(args.len());
+        for a in args.iter() {
+            fargs.push(a.eval(env)?);
+        }
+        match *f {
+            MalFunc(MalFuncData{code: Some(ref lambdas), env: ref fenv, ref name, ..},_) => {
+                let (lambda, frame) = bind_args(lambdas, name.as_deref(), fenv, fargs)?;
+                if pos.func.is_none() {
+                    pos.call = pos.form.clone();
+                }
+                pos.func = Some(f.clone());
+                Ok(Step::Eval(lambda.body.clone(), frame))
+            },
+            _ => value(f.apply(fargs)?),
+        }
+    })
+}
+
+pub fn compile(node: Node) -> Code {
+    match node {
+        Node::Const(v) => Rc::new(move |_, _| value(v.clone())),
+        Node::Local(depth, i) => Rc::new(move |env, _| value(env_slot(env, depth, i))),
+        Node::Global(var, name) => Rc::new(move |_, _| value(var_value(&var, &name)?)),
+        Node::Vector(items) => {
+            let items = compile_all(items);
+            Rc::new(move |env, _| {
+                let mut v = Vec::with_capacity(items.len());
+                for item in items.iter() {
+                    v.push(item.eval(env)?);
+                }
+                value(vector(v))
+            })
+        },
+        Node::Map(entries) => {
+            let entries: Vec<(Operand, Operand)> = entries.into_iter()
+                .map(|(k, v)| (operand(k), operand(v))).collect();
+            Rc::new(move |env, _| {
+                let mut hm = MalMap::new();
+                for (key, val) in entries.iter() {
+                    hm.insert(key.eval(env)?, val.eval(env)?);
+                }
+                value(hash_map(hm))
+            })
+        },
+        Node::Set(items) => {
+            let items = compile_all(items);
+            Rc::new(move |env, _| {
+                let mut s = MalSet::new();
+                for item in items.iter() {
+                    s.insert(item.eval(env)?);
+                }
+                value(set(s))
+            })
+        },
+        Node::DefGlobal(var, name, val, is_macro) => {
+            let val = operand(*val);
+            Rc::new(move |env, _| {
+                let r = defined(val.eval(env)?, &name, is_macro)?;
+                *var.borrow_mut() = Some(r.clone());
+                value(r)
+            })
+        },
+        Node::DefLocal(i, name, val, is_macro) => {
+            let val = operand(*val);
+            Rc::new(move |env, _| {
+                let r = defined(val.eval(env)?, &name, is_macro)?;
+                env_set_slot(env, i, r.clone());
+                value(r)
+            })
+        },
+        Node::Let(inits, nslots, body) => {
+            let inits = compile_inits(inits);
+            let body = compile(*body);
+            Rc::new(move |env, pos| {
+                let frame = env_frame(Some(env.clone()), vec![_nil(); nslots]);
+                bind_all(&inits, &frame)?;
+                body(&frame, pos)
+            })
+        },
+        Node::Bind(inits, body) => {
+            let inits = compile_inits(inits);
+            let body = compile(*body);
+            Rc::new(move |env, pos| {
+                bind_all(&inits, env)?;
+                body(env, pos)
+            })
+        },
+        Node::If(cond, then, els) => {
+            let cond = operand(*cond);
+            let then = compile(*then);
+            let els = els.map(|e| compile(*e));
+            Rc::new(move |env, pos| {
+                match *cond.eval(env)? {
+                    False | Nil => match els {
+                        Some(ref els) => els(env, pos),
+                        None => value(_nil()),
+                    },
+                    _ => then(env, pos),
+                }
+            })
+        },
+        Node::Do(forms, last) => {
+            let forms = compile_all(forms);
+            let last = compile(*last);
+            Rc::new(move |env, pos| {
+                for form in forms.iter() {
+                    form.eval(env)?;
+                }
+                last(env, pos)
+            })
+        },
+        Node::Fn(name, arities, form) => {
+            let lambdas: Rc<[Lambda]> = arities.into_iter().map(|a| {
+                Lambda{nfixed: a.nfixed, variadic: a.variadic, nslots: a.nslots,
+                       body: compile(a.body)}
+            }).collect::<Vec<_>>().into();
+            let fname = name.as_ref().map(|n| n.to_string());
+            Rc::new(move |env, _| {
+                let make = |env| malfuncd(MalFuncData{eval,
+                                                      exp: form.clone(),
+                                                      env,
+                                                      params: _nil(),
+                                                      is_macro: false,
+                                                      name: fname.clone(),
+                                                      code: Some(lambdas.clone())},
+                                          _nil());
+                if fname.is_none() {
+                    return value(make(env.clone()));
+                }
+                // a named fn* runs in a frame binding its name to itself
+                let frame = env_frame(Some(env.clone()), vec![_nil()]);
+                let f = make(frame.clone());
+                env_set_slot(&frame, 0, f.clone());
+                value(f)
+            })
+        },
+        Node::Call(f, args) => compile_call(*f, args, None),
+        Node::Binding(binds, body) => {
+            let binds: Vec<(Var, Symbol, Operand)> = binds.into_iter()
+                .map(|(var, name, init)| (var, name, operand(init))).collect();
+            let body = operand(*body);
+            Rc::new(move |env, _| {
+                let mut vals = Vec::with_capacity(binds.len());
+                for (_, _, init) in binds.iter() {
+                    vals.push(init.eval(env)?);
+                }
+                for (var, name, _) in binds.iter() {
+                    var_value(var, name)?;
+                }
+                for ((var, _, _), val) in binds.iter().zip(vals) {
+                    var_bind(var, val);
+                }
+                let res = body.eval(env);
+                for (var, _, _) in binds.iter() {
+                    var_unbind(var);
+                }
+                value(res?)
+            })
+        },
+        Node::Ns(name, specs) => {
+            let name = name.map(|name| symbol(&name));
+            let specs: Vec<Operand> = specs.into_iter().map(operand).collect();
+            Rc::new(move |env, _| {
+                let root = env_root(env);
+                if let Some(ref name) = name {
+                    env_set(&root, symbol("*ns*"), name.clone());
+                }
+                for spec in specs.iter() {
+                    ns::require(&root, &spec.eval(env)?)?;
+                }
+                value(_nil())
+            })
+        },
+        Node::Try(body, catches, finally) => {
+            let body = operand(*body);
+            let catches: Vec<CatchCode> = catches.into_iter().map(|c| CatchCode{
+                selector: c.selector.map(operand),
+                nslots: c.nslots,
+                handler: operand(c.handler),
+            }).collect();
+            let finally = finally.map(|f| operand(*f));
+            Rc::new(move |env, _| {
+                let res = match body.eval(env) {
+                    Ok(res) => Ok(res),
+                    Err(err) => run_catch(&catches, env, err),
+                };
+                if let Some(ref finally) = finally {
+                    finally.eval(env)?;
+                }
+                value(res?)
+            })
+        },
+        Node::Eval(arg) => {
+            let arg = operand(*arg);
+            Rc::new(move |env, _| {
+                let ast = arg.eval(env)?;
+                let root = env_root(env);
+                if is_toplevel_do(&ast) {
+                    return value(eval(ast, root)?);
+                }
+                Ok(Step::Eval(compile(analyze(&ast, &root)?), root))
+            })
+        },
+        Node::At(form, inner) => match *inner {
+            Node::Call(f, args) => compile_call(*f, args, Some(form)),
+            inner => {
+                let inner = compile(inner);
+                Rc::new(move |env, pos| {
+                    pos.form = Some(form.clone());
+                    inner(env, pos)
+                })
+            },
+        },
+        Node::Loop(slots, parts, body) => {
+            let parts = compile_inits(parts);
+            let body = compile(*body);
+            Rc::new(move |env, pos| {
+                // the frame the loop carries on in, once env is captured
+                let mut next: Option<Env> = None;
+                loop {
+                    let frame = next.as_ref().unwrap_or(env);
+                    let vals = match body(frame, pos)? {
+                        Step::Recur(vals) => vals,
+                        step => return Ok(step),
+                    };
+                    if let Some(frame) = env_recur(frame, &slots, vals) {
+                        next = Some(frame);
+                    }
+                    bind_all(&parts, next.as_ref().unwrap_or(env))?;
+                }
+            })
+        },
+        Node::Recur(args) => {
+            let args = compile_all(args);
+            Rc::new(move |env, _| {
+                let mut vals = Vec::with_capacity(args.len());
+                for a in args.iter() {
+                    vals.push(a.eval(env)?);
+                }
+                Ok(Step::Recur(vals))
+            })
+        },
+        Node::Part(from, Part::Nth(i)) => {
+            Rc::new(move |env, _| value(nth_part(&env_slot(env, 0, from), i)?))
+        },
+        Node::Part(from, Part::Rest(i)) => {
+            Rc::new(move |env, _| value(rest_part(&env_slot(env, 0, from), i)?))
+        },
+        Node::Part(from, Part::Map) => {
+            Rc::new(move |env, _| value(map_part(&env_slot(env, 0, from))?))
+        },
+        Node::Part(from, Part::Key(key, default)) => {
+            let default = default.map(|d| operand(*d));
+            Rc::new(move |env, _| {
+                // the slot was made a map (or nil) by a Part::Map
+                if let Hash_Map(ref hm,_) = *env_slot(env, 0, from) {
+                    if let Some(v) = hm.get(&key) {
+                        return value(v.clone());
+                    }
+                }
+                match default {
+                    Some(ref d) => value(d.eval(env)?),
+                    None => value(_nil()),
+                }
+            })
+        },
+        Node::Fail(e) => Rc::new(move |_, _| Err(e.clone())),
+    }
+}
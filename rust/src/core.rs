@@ -1,25 +1,31 @@
 #![allow(dead_code)]
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 //use std::num::ToPrimitive;
-use num::traits::ToPrimitive;
+use num::BigInt;
+use num::traits::{ToPrimitive, FromPrimitive, Zero};
 use time;
 
-use types::{MalVal,MalRet,err_val,err_str,err_string,
-            _nil,_true,_false,_int,string,
-            list,vector,listm,vectorm,hash_mapm,func,funcm,malfuncd};
-use types::MalType::{Nil, Int, Strn, List, Vector, Hash_Map, Func, MalFunc, Atom};
+use types::{MalVal,MalRet,MalError,MalSeq,MalMap,MalSet,Kind,ExInfoData,
+            err_val,err_kind,err_type,err_arity,
+            _nil,_true,_false,_int,_bigint,_float,string,
+            list,vector,listm,vectorm,hash_mapm,setm,func,funcm,native_funcd,malfuncd,
+            ex_infom};
+use types::MalType::{Nil, Int, Float, Big, Strn, List, Vector, Hash_Map, Set, Func, NativeFunc, MalFunc, Atom};
+use types::MalType::ExInfo;
 use types;
 use readline;
 use reader;
 use printer;
+use gc;
 
 // General functions
 fn equal_q(a: Vec<MalVal>) -> MalRet {
     if a.len() != 2 {
-        return err_str("Wrong arity to equal? call");
+        return err_arity("equal?");
     }
     if a[0] == a[1] {Ok(_true())} else {Ok(_false())}
 }
@@ -27,9 +33,54 @@ fn equal_q(a: Vec<MalVal>) -> MalRet {
 // Errors/Exceptions
 fn throw(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to throw call");
+        return err_arity("throw");
     }
-    err_val(a[0].clone())
+    // a caught native error is thrown again as itself, keeping its kind
+    match (types::caught_kind(&a[0]), &*a[0]) {
+        (Some(kind), Strn(s)) => err_kind(kind, s.clone()),
+        _ => err_val(a[0].clone()),
+    }
+}
+
+// Errors
+fn ex_info(a: Vec<MalVal>) -> MalRet {
+    if a.len() != 2 && a.len() != 3 {
+        return err_arity("ex-info");
+    }
+    let message = match *a[0] {
+        Strn(ref s) => s.clone(),
+        _ => return err_type("ex-info: message must be a string"),
+    };
+    let data = match *a[1] {
+        Hash_Map(..) => a[1].clone(),
+        Nil => types::hash_map(MalMap::new()),
+        _ => return err_type("ex-info: data must be a map"),
+    };
+    Ok(types::ex_info(message, data, a.get(2).cloned().unwrap_or_else(_nil)))
+}
+
+// The part of an ex-info (or of a caught native error) that get picks
+// out, or nil for any other value
+fn ex_part(name: &str, a: Vec<MalVal>, get: fn(&ExInfoData) -> MalVal) -> MalRet {
+    if a.len() != 1 {
+        return err_arity(name);
+    }
+    match *a[0] {
+        ExInfo(ref e,_) => Ok(get(e)),
+        _ => Ok(types::caught_ex_info(&a[0]).map_or_else(_nil, |e| get(&e))),
+    }
+}
+
+fn ex_data(a: Vec<MalVal>) -> MalRet {
+    ex_part("ex-data", a, |e| e.data.clone())
+}
+
+fn ex_message(a: Vec<MalVal>) -> MalRet {
+    ex_part("ex-message", a, |e| string(e.message.clone()))
+}
+
+fn ex_cause(a: Vec<MalVal>) -> MalRet {
+    ex_part("ex-cause", a, |e| e.cause.clone())
 }
 
 // String routines
@@ -52,77 +103,214 @@ fn println(a: Vec<MalVal>) -> MalRet {
 }
 
 fn readline(a: Vec<MalVal>) -> MalRet {
+    if a.len() != 1 {
+        return err_arity("readline");
+    }
     match *a[0] {
-        Strn(ref a0) => match readline::mal_readline(&a0) {
+        Strn(ref a0) => match readline::mal_readline(a0) {
             Some(line) => Ok(string(line)),
             None       => err_val(_nil()),
         },
-        _ => err_str("read_string called with non-string"),
+        _ => err_type("readline called with non-string"),
     }
 }
 
+// (read-string s) or (read-string s filename)
 fn read_string(a: Vec<MalVal>) -> MalRet {
+    if a.is_empty() || a.len() > 2 {
+        return err_arity("read-string");
+    }
+    let file = if a.len() > 1 { a[1].clone() } else { _nil() };
     match *a[0] {
-        Strn(ref a0) => reader::read_str(a0.to_string()),
-        _ => err_str("read_string called with non-string"),
+        Strn(ref a0) => reader::read_str_from(a0.to_string(), file),
+        _ => err_type("read_string called with non-string"),
     }
 }
 
 fn slurp(a: Vec<MalVal>) -> MalRet {
+    if a.len() != 1 {
+        return err_arity("slurp");
+    }
     match *a[0] {
         Strn(ref a0) => {
             let mut s = String::new();
             match File::open(a0).and_then(|mut f| f.read_to_string(&mut s)) {
                 Ok(_) => Ok(string(s)),
-                Err(e) => err_string(e.to_string()),
+                Err(e) => err_kind(Kind::Io, e.to_string()),
             }
         },
-        _ => err_str("slurp called with non-string"),
+        _ => err_type("slurp called with non-string"),
     }
 }
 
 
 // Numeric functions
-fn int_op<F>(f: F, a: Vec<MalVal>) -> MalRet
-    where F: FnOnce(isize, isize) -> isize
+fn num_big(mv: &MalVal) -> Option<BigInt> {
+    match **mv {
+        Int(i) => Some(BigInt::from(i)),
+        Big(ref b) => Some(b.clone()),
+        _ => None,
+    }
+}
+
+fn num_f64(mv: &MalVal) -> Option<f64> {
+    match **mv {
+        Int(i) => Some(i as f64),
+        Big(ref b) => b.to_f64(),
+        Float(f) => Some(f),
+        _ => None,
+    }
+}
+
+// Shared argument check for the arithmetic and comparison builtins:
+// at least min arguments, all of them numbers
+fn check_nums(name: &str, min: usize, a: &[MalVal]) -> Result<(), MalError> {
+    if a.len() < min {
+        return err_arity(name);
+    }
+    for (i, mv) in a.iter().enumerate() {
+        match **mv {
+            Int(_) | Big(_) | Float(_) => (),
+            _ => return err_type(format!(
+                    "{}: argument {} is not a number: {}",
+                    name, i + 1, mv.pr_str(true))),
+        }
+    }
+    Ok(())
+}
+
+// Tries fi on two ints, falling back to fb on big integers when it
+// overflows (fb returns None for division by zero). Otherwise promotes
+// both arguments to floats and applies ff.
+fn num_op<F, G, H>(fi: F, fb: G, ff: H, x: &MalVal, y: &MalVal) -> MalRet
+    where F: FnOnce(isize, isize) -> Option<isize>,
+          G: FnOnce(BigInt, BigInt) -> Option<BigInt>,
+          H: FnOnce(f64, f64) -> f64
 {
-    match *a[0] {
-        Int(a0) => match *a[1] {
-            Int(a1) => Ok(_int(f(a0,a1))),
-            _ => err_str("second arg must be an int"),
-        },
-        _ => err_str("first arg must be an int"),
+    if let (&Int(x), &Int(y)) = (&**x, &**y) {
+        if let Some(r) = fi(x,y) {
+            return Ok(_int(r));
+        }
+    }
+    if let (Some(x), Some(y)) = (num_big(x), num_big(y)) {
+        return match fb(x,y) {
+            Some(r) => Ok(_bigint(r)),
+            None => err_kind(Kind::Arithmetic, "Divide by zero"),
+        };
+    }
+    match (num_f64(x), num_f64(y)) {
+        (Some(x), Some(y)) => Ok(_float(ff(x,y))),
+        _ => err_type("arithmetic on non-number"),
+    }
+}
+
+fn num_cmp(x: &MalVal, y: &MalVal) -> Option<Ordering> {
+    if let (&Int(x), &Int(y)) = (&**x, &**y) {
+        return Some(x.cmp(&y));
+    }
+    if let (Some(x), Some(y)) = (num_big(x), num_big(y)) {
+        return Some(x.cmp(&y));
+    }
+    num_f64(x)?.partial_cmp(&num_f64(y)?)
+}
+
+fn plus(x: &MalVal, y: &MalVal) -> MalRet {
+    num_op(|i,j| i.checked_add(j), |x,y| Some(x+y), |x,y| x+y, x, y)
+}
+fn minus(x: &MalVal, y: &MalVal) -> MalRet {
+    num_op(|i,j| i.checked_sub(j), |x,y| Some(x-y), |x,y| x-y, x, y)
+}
+fn times(x: &MalVal, y: &MalVal) -> MalRet {
+    num_op(|i,j| i.checked_mul(j), |x,y| Some(x*y), |x,y| x*y, x, y)
+}
+fn divide(x: &MalVal, y: &MalVal) -> MalRet {
+    num_op(|i,j| i.checked_div(j),
+           |x,y| if y.is_zero() { None } else { Some(x/y) },
+           |x,y| x/y, x, y)
+}
+
+// Left fold of op over the arguments, starting from init
+fn fold_nums<F>(init: &MalVal, a: &[MalVal], op: F) -> MalRet
+    where F: Fn(&MalVal, &MalVal) -> MalRet
+{
+    let mut acc = init.clone();
+    for mv in a.iter() {
+        acc = op(&acc, mv)?;
     }
+    Ok(acc)
 }
 
-fn bool_op<F>(f: F, a: Vec<MalVal>) -> MalRet
-    where F: FnOnce(isize, isize) -> bool
+pub fn add(a: Vec<MalVal>) -> MalRet {
+    check_nums("+", 0, &a)?;
+    fold_nums(&_int(0), &a, plus)
+}
+pub fn sub(a: Vec<MalVal>) -> MalRet {
+    check_nums("-", 1, &a)?;
+    match a.len() {
+        1 => minus(&_int(0), &a[0]),
+        _ => fold_nums(&a[0], &a[1..], minus),
+    }
+}
+pub fn mul(a: Vec<MalVal>) -> MalRet {
+    check_nums("*", 0, &a)?;
+    fold_nums(&_int(1), &a, times)
+}
+pub fn div(a: Vec<MalVal>) -> MalRet {
+    check_nums("/", 1, &a)?;
+    match a.len() {
+        1 => divide(&_int(1), &a[0]),
+        _ => fold_nums(&a[0], &a[1..], divide),
+    }
+}
+
+// True if every adjacent pair of arguments is ordered as f requires
+fn cmp_op<F>(name: &str, f: F, a: Vec<MalVal>) -> MalRet
+    where F: Fn(Ordering) -> bool
 {
+    check_nums(name, 1, &a)?;
+    for w in a.windows(2) {
+        match num_cmp(&w[0], &w[1]) {
+            Some(o) if f(o) => (),
+            _ => return Ok(_false()),
+        }
+    }
+    Ok(_true())
+}
+
+pub fn lt (a: Vec<MalVal>) -> MalRet { cmp_op("<",  |o| o == Ordering::Less, a) }
+pub fn lte(a: Vec<MalVal>) -> MalRet { cmp_op("<=", |o| o != Ordering::Greater, a) }
+pub fn gt (a: Vec<MalVal>) -> MalRet { cmp_op(">",  |o| o == Ordering::Greater, a) }
+pub fn gte(a: Vec<MalVal>) -> MalRet { cmp_op(">=", |o| o != Ordering::Less, a) }
+
+pub fn int(a: Vec<MalVal>) -> MalRet {
+    if a.len() != 1 {
+        return err_arity("int");
+    }
     match *a[0] {
-        Int(a0) => match *a[1] {
-            Int(a1) => {
-                match f(a0,a1) {
-                    true => Ok(_true()),
-                    false => Ok(_false()),
-                }
-            },
-            _ => err_str("second arg must be an int"),
+        Int(_) | Big(_) => Ok(a[0].clone()),
+        // truncates toward zero
+        Float(f) => match BigInt::from_f64(f) {
+            Some(b) => Ok(_bigint(b)),
+            None => err_kind(Kind::Range, format!("int: {} is out of range", a[0].pr_str(true))),
         },
-        _ => err_str("first arg must be an int"),
+        _ => err_type("int called on non-number"),
     }
 }
 
-pub fn add(a: Vec<MalVal>) -> MalRet { int_op(|i,j| { i+j }, a) }
-pub fn sub(a: Vec<MalVal>) -> MalRet { int_op(|i,j| { i-j }, a) }
-pub fn mul(a: Vec<MalVal>) -> MalRet { int_op(|i,j| { i*j }, a) }
-pub fn div(a: Vec<MalVal>) -> MalRet { int_op(|i,j| { i/j }, a) }
-
-pub fn lt (a: Vec<MalVal>) -> MalRet { bool_op(|i,j| { i<j }, a) }
-pub fn lte(a: Vec<MalVal>) -> MalRet { bool_op(|i,j| { i<=j }, a) }
-pub fn gt (a: Vec<MalVal>) -> MalRet { bool_op(|i,j| { i>j }, a) }
-pub fn gte(a: Vec<MalVal>) -> MalRet { bool_op(|i,j| { i>=j }, a) }
+pub fn double(a: Vec<MalVal>) -> MalRet {
+    if a.len() != 1 {
+        return err_arity("double");
+    }
+    match num_f64(&a[0]) {
+        Some(f) => Ok(_float(f)),
+        None => err_type("double called on non-number"),
+    }
+}
 
-pub fn time_ms(_a: Vec<MalVal>) -> MalRet {
+pub fn time_ms(a: Vec<MalVal>) -> MalRet {
+    if !a.is_empty() {
+        return err_arity("time-ms");
+    }
     //let x = time::now();
     let now = time::get_time();
     let now_ms = (now.sec * 1000).to_isize().unwrap() +
@@ -134,108 +322,190 @@ pub fn time_ms(_a: Vec<MalVal>) -> MalRet {
 // Hash Map functions
 pub fn assoc(a: Vec<MalVal>) -> MalRet {
     if a.len() < 3 {
-        return err_str("Wrong arity to assoc call");
+        return err_arity("assoc");
     }
     match *a[0] {
         Hash_Map(ref hm,_) => types::_assoc(hm, a[1..].to_vec()),
         Nil => types::hash_mapv(a[1..].to_vec()),
-        _ => err_str("assoc onto non-hash map"),
+        _ => err_type("assoc onto non-hash map"),
     }
 }
 
 pub fn dissoc(a: Vec<MalVal>) -> MalRet {
     if a.len() < 2 {
-        return err_str("Wrong arity to dissoc call");
+        return err_arity("dissoc");
     }
     match *a[0] {
         Hash_Map(ref hm,_) => types::_dissoc(hm, a[1..].to_vec()),
         Nil => Ok(_nil()),
-        _ => err_str("dissoc onto non-hash map"),
+        _ => err_type("dissoc onto non-hash map"),
     }
 }
 
 pub fn get(a: Vec<MalVal>) -> MalRet {
     if a.len() != 2 {
-        return err_str("Wrong arity to get call");
+        return err_arity("get");
     }
     let hm = match *a[0] {
         Hash_Map(ref hm,_) => hm,
+        Set(ref s,_) => return Ok(if s.contains(&a[1]) { a[1].clone() } else { _nil() }),
         Nil => return Ok(_nil()),
-        _ => return err_str("get on non-hash map"),
+        _ => return err_type("get on non-hash map"),
     };
-    match *a[1] {
-        Strn(ref key) => {
-            match hm.get(key) {
-                Some(v) => Ok(v.clone()),
-                None    => Ok(_nil()),
-            }
-        },
-        _ => err_str("get with non-string key"),
+    match hm.get(&a[1]) {
+        Some(v) => Ok(v.clone()),
+        None    => Ok(_nil()),
     }
 }
 
 pub fn contains_q(a: Vec<MalVal>) -> MalRet {
     if a.len() != 2 {
-        return err_str("Wrong arity to contains? call");
+        return err_arity("contains?");
     }
     let hm = match *a[0] {
         Hash_Map(ref hm,_) => hm,
+        Set(ref s,_) => return Ok(if s.contains(&a[1]) { _true() } else { _false() }),
         Nil => return Ok(_false()),
-        _ => return err_str("contains? on non-hash map"),
+        _ => return err_type("contains? on non-hash map"),
     };
-    match *a[1] {
-        Strn(ref key) => {
-            match hm.contains_key(key) {
-                true  => Ok(_true()),
-                false => Ok(_false()),
-            }
-        }
-        _ => err_str("contains? with non-string key"),
+    match hm.contains_key(&a[1]) {
+        true  => Ok(_true()),
+        false => Ok(_false()),
     }
 }
 
 pub fn keys(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to keys call");
+        return err_arity("keys");
     }
     let hm = match *a[0] {
         Hash_Map(ref hm,_) => hm,
         Nil => return Ok(_nil()),
-        _ => return err_str("contains? on non-hash map"),
+        _ => return err_type("keys on non-hash map"),
     };
-    Ok(list(hm.keys().map(|s| string(s.to_string())).collect()))
+    Ok(list(hm.keys().cloned().collect::<MalSeq>()))
 }
 
 pub fn vals(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to values call");
+        return err_arity("values");
     }
     let hm = match *a[0] {
         Hash_Map(ref hm,_) => hm,
         Nil => return Ok(_nil()),
-        _ => return err_str("contains? on non-hash map"),
+        _ => return err_type("vals on non-hash map"),
     };
-    Ok(list(hm.values().map(|s| s.clone()).collect()))
+    Ok(list(hm.values().cloned().collect::<MalSeq>()))
+}
+
+// Set functions
+fn set_of(name: &str, v: &MalVal) -> Result<MalSet, MalError> {
+    match **v {
+        Set(ref s,_) => Ok(s.clone()),
+        Nil => Ok(MalSet::new()),
+        _ => err_type(format!("{} called with non-set", name)),
+    }
+}
+
+// (set coll): a set of the items of coll
+fn set(a: Vec<MalVal>) -> MalRet {
+    if a.len() != 1 {
+        return err_arity("set");
+    }
+    match items(&a[0]) {
+        Some(v) => Ok(types::set(v.into_iter().collect())),
+        None if matches!(*a[0], Nil) => Ok(types::set(MalSet::new())),
+        None => err_type("set called with non-collection"),
+    }
+}
+
+fn disj(a: Vec<MalVal>) -> MalRet {
+    if a.is_empty() {
+        return err_arity("disj");
+    }
+    match *a[0] {
+        Set(ref s,_) => {
+            let mut new_s = s.clone();
+            for k in a.iter().skip(1) {
+                new_s.remove(k);
+            }
+            Ok(types::set(new_s))
+        },
+        Nil => Ok(_nil()),
+        _ => err_type("disj called with non-set"),
+    }
+}
+
+fn union(a: Vec<MalVal>) -> MalRet {
+    let mut res = MalSet::new();
+    for s in a.iter() {
+        res = res.union(set_of("union", s)?);
+    }
+    Ok(types::set(res))
+}
+
+fn intersection(a: Vec<MalVal>) -> MalRet {
+    if a.is_empty() {
+        return err_arity("intersection");
+    }
+    let mut res = set_of("intersection", &a[0])?;
+    for s in a.iter().skip(1) {
+        res = res.intersection(set_of("intersection", s)?);
+    }
+    Ok(types::set(res))
+}
+
+fn difference(a: Vec<MalVal>) -> MalRet {
+    if a.is_empty() {
+        return err_arity("difference");
+    }
+    let mut res = set_of("difference", &a[0])?;
+    for s in a.iter().skip(1) {
+        res = res.relative_complement(set_of("difference", s)?);
+    }
+    Ok(types::set(res))
+}
+
+// (subset? a b): whether every item of a is in b
+fn subset_q(a: Vec<MalVal>) -> MalRet {
+    if a.len() != 2 {
+        return err_arity("subset?");
+    }
+    let (sub, sup) = (set_of("subset?", &a[0])?, set_of("subset?", &a[1])?);
+    Ok(if sub.is_subset(sup) { _true() } else { _false() })
 }
 
 // Sequence functions
+
+// The items of a list, vector or set, in order (a set's in no particular
+// one), for the functions that walk a sequence
+fn items(v: &MalVal) -> Option<MalSeq> {
+    match **v {
+        List(ref v,_) | Vector(ref v,_) => Some(v.clone()),
+        Set(ref s,_) => Some(s.iter().cloned().collect()),
+        _ => None,
+    }
+}
+
 pub fn cons(a: Vec<MalVal>) -> MalRet {
-    match *a[1] {
-        List(ref v,_) | Vector(ref v,_) => {
-            let mut new_v = v.clone();
-            new_v.insert(0, a[0].clone());
+    if a.len() != 2 {
+        return err_arity("cons");
+    }
+    match items(&a[1]) {
+        Some(mut new_v) => {
+            new_v.push_front(a[0].clone());
             Ok(list(new_v))
         },
-        _ => err_str("Second arg to cons not a sequence"),
+        None => err_type("Second arg to cons not a sequence"),
     }
 }
 
 pub fn concat(a: Vec<MalVal>) -> MalRet {
-    let mut new_v: Vec<MalVal> = vec![];
+    let mut new_v = MalSeq::new();
     for lst in a.iter() {
-        match **lst {
-            List(ref l,_) | Vector(ref l,_) => new_v.extend(l.clone()),
-            _ => return err_str("concat called with non-sequence"),
+        match items(lst) {
+            Some(l) => new_v.append(l),
+            None => return err_type("concat called with non-sequence"),
         }
     }
     Ok(list(new_v))
@@ -243,23 +513,23 @@ pub fn concat(a: Vec<MalVal>) -> MalRet {
 
 pub fn nth(a: Vec<MalVal>) -> MalRet {
     if a.len() != 2 {
-        return err_str("Wrong arity to nth call");
+        return err_arity("nth");
     }
     let seq = match *a[0] {
         List(ref v,_) | Vector(ref v,_) => v,
-        _ => return err_str("nth called with non-sequence"),
+        _ => return err_type("nth called with non-sequence"),
     };
     let idx = match *a[1] {
         Int(i) => {
             match i.to_usize() {
                 Some(ui) => ui,
-                None => return Ok(_nil()),
+                None => return err_kind(Kind::Range, "nth: index out of range"),
             }
         },
-        _ => return err_str("nth called with non-integer index"),
+        _ => return err_type("nth called with non-integer index"),
     };
     if idx >= seq.len() {
-        err_str("nth: index out of range")
+        err_kind(Kind::Range, "nth: index out of range")
     } else {
         Ok(seq[idx].clone())
     }
@@ -267,14 +537,14 @@ pub fn nth(a: Vec<MalVal>) -> MalRet {
 
 pub fn first(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to first call");
+        return err_arity("first");
     }
-    let seq = match *a[0] {
-        List(ref v,_) | Vector(ref v,_) => v,
-        Nil => return Ok(_nil()),
-        _ => return err_str("first called with non-sequence"),
+    let seq = match items(&a[0]) {
+        Some(seq) => seq,
+        None if matches!(*a[0], Nil) => return Ok(_nil()),
+        None => return err_type("first called with non-sequence"),
     };
-    if seq.len() == 0 {
+    if seq.is_empty() {
         Ok(_nil())
     } else {
         Ok(seq[0].clone())
@@ -283,23 +553,23 @@ pub fn first(a: Vec<MalVal>) -> MalRet {
 
 pub fn rest(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to rest call");
+        return err_arity("rest");
     }
-    let seq = match *a[0] {
-        List(ref v,_) | Vector(ref v,_) => v,
-        Nil => return Ok(list(vec![])),
-        _ => return err_str("rest called with non-sequence"),
+    let seq = match items(&a[0]) {
+        Some(seq) => seq,
+        None if matches!(*a[0], Nil) => return Ok(list(vec![])),
+        None => return err_type("rest called with non-sequence"),
     };
-    if seq.len() == 0 {
+    if seq.is_empty() {
         Ok(list(vec![]))
     } else {
-        Ok(list(seq[1..].to_vec()))
+        Ok(list(seq.skip(1)))
     }
 }
 
 pub fn empty_q(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to empty? call");
+        return err_arity("empty?");
     }
     match *a[0] {
         List(ref v,_) | Vector(ref v,_) => {
@@ -308,97 +578,102 @@ pub fn empty_q(a: Vec<MalVal>) -> MalRet {
                 _ => Ok(_false()),
             }
         },
-        _ => err_str("empty? called on non-sequence"),
+        Set(ref s,_) => Ok(if s.is_empty() { _true() } else { _false() }),
+        _ => err_type("empty? called on non-sequence"),
     }
 }
 
 pub fn count(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to count call");
+        return err_arity("count");
     }
     match *a[0] {
         List(ref v,_) | Vector(ref v,_) => Ok(_int(v.len().to_isize().unwrap())),
+        Set(ref s,_) => Ok(_int(s.len().to_isize().unwrap())),
         Nil => Ok(_int(0)),
-        _ => err_str("count called on non-sequence"),
+        _ => err_type("count called on non-sequence"),
     }
 }
 
 pub fn apply(a: Vec<MalVal>) -> MalRet {
     if a.len() < 2 {
-        return err_str("apply call needs 2 or more arguments");
+        return err_kind(Kind::Arity, "apply call needs 2 or more arguments");
     }
-    let ref f = a[0];
+    let f = &a[0];
     let mut args = a[1..a.len()-1].to_vec();
-    match *a[a.len()-1] {
-        List(ref v, _) | Vector(ref v, _) => {
-            args.extend(v.clone());
+    match items(&a[a.len()-1]) {
+        Some(v) => {
+            args.extend(v);
             f.apply(args)
         },
-        _ => err_str("apply call with non-sequence"),
+        None => err_type("apply call with non-sequence"),
     }
 }
 
 pub fn map(a: Vec<MalVal>) -> MalRet {
     if a.len() != 2 {
-        return err_str("Wrong arity to map call");
+        return err_arity("map");
     }
     let mut results:Vec<MalVal> = vec![];
-    match *a[1] {
-        List(ref v,_) | Vector(ref v,_) => {
+    match items(&a[1]) {
+        Some(v) => {
             for mv in v.iter() {
-                let res = try!(a[0].apply(vec![mv.clone()]));
+                let res = a[0].apply(vec![mv.clone()])?;
                 results.push(res);
             }
         },
-        _ => return err_str("map call with non-sequence"),
+        None => return err_type("map call with non-sequence"),
     }
     Ok(list(results))
 }
 
 pub fn conj(a: Vec<MalVal>) -> MalRet {
     if a.len() < 2 {
-        return err_str("Wrong arity to conj call");
+        return err_arity("conj");
     }
-    let mut new_v: Vec<MalVal> = vec![];
     match *a[0] {
         List(ref l,_) => {
-            new_v.extend(l.clone());
+            let mut new_v = l.clone();
             for mv in a.iter().skip(1) {
-                new_v.insert(0, mv.clone());
+                new_v.push_front(mv.clone());
             }
             Ok(list(new_v))
         }
         Vector(ref l,_) => {
-            new_v.extend(l.clone());
+            let mut new_v = l.clone();
             for mv in a.iter().skip(1) {
-                new_v.push(mv.clone());
+                new_v.push_back(mv.clone());
             }
             Ok(vector(new_v))
         }
-        _ => err_str("conj called with non-sequence"),
+        Set(ref s,_) => {
+            let mut new_s = s.clone();
+            for mv in a.iter().skip(1) {
+                new_s.insert(mv.clone());
+            }
+            Ok(types::set(new_s))
+        }
+        _ => err_type("conj called with non-sequence"),
     }
 }
 
 pub fn seq(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to seq call");
+        return err_arity("seq");
     }
     let mut new_v: Vec<MalVal> = vec![];
     match *a[0] {
-        List(ref l,_) |
-        Vector(ref l,_) => {
-            if l.len() == 0 {
+        List(..) | Vector(..) | Set(..) => {
+            let l = items(&a[0]).unwrap_or_default();
+            if l.is_empty() {
                 Ok(_nil())
             } else {
-                new_v.extend(l.clone());
-                Ok(list(new_v))
+                Ok(list(l))
             }
         },
         Strn(ref s) => {
-            if s.len() == 0 {
+            if s.is_empty() {
                 Ok(_nil())
-            } else if s.starts_with("\u{29e}") {
-                err_str("seq: called with non-sequence")
             } else {
                 for c in s.chars() {
                     new_v.push(string(c.to_string()));
@@ -407,7 +682,7 @@ pub fn seq(a: Vec<MalVal>) -> MalRet {
             }
         },
         Nil => Ok(_nil()),
-        _ => err_str("seq: called with non-sequence"),
+        _ => err_type("seq: called with non-sequence"),
     }
 }
 
@@ -416,47 +691,53 @@ pub fn seq(a: Vec<MalVal>) -> MalRet {
 // Metadata functions
 fn with_meta(a: Vec<MalVal>) -> MalRet {
     if a.len() != 2 {
-        return err_str("Wrong arity to with-meta call");
+        return err_arity("with-meta");
     }
     let meta = a[1].clone();
     match *a[0] {
         List(ref v,_) => Ok(listm(v.clone(), meta)),
         Vector(ref v,_) => Ok(vectorm(v.clone(), meta)),
         Hash_Map(ref hm,_) => Ok(hash_mapm(hm.clone(), meta)),
+        Set(ref s,_) => Ok(setm(s.clone(), meta)),
         MalFunc(ref mfd,_) => Ok(malfuncd(mfd.clone(), meta)),
         Func(f,_) => Ok(funcm(f, meta)),
-        _ => err_str("type does not support metadata"),
+        NativeFunc(ref nfd,_) => Ok(native_funcd(nfd.clone(), meta)),
+        ExInfo(ref e,_) => Ok(ex_infom(e.clone(), meta)),
+        _ => err_type("type does not support metadata"),
     }
 }
 
 fn meta(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to meta call");
+        return err_arity("meta");
     }
     match *a[0] {
         List(_,ref meta) |
         Vector(_,ref meta) |
         Hash_Map(_,ref meta) |
+        Set(_,ref meta) |
+        ExInfo(_,ref meta) |
         MalFunc(_,ref meta) |
+        NativeFunc(_,ref meta) |
         Func(_,ref meta) => Ok(meta.clone()),
-        _ => err_str("type does not support metadata"),
+        _ => err_type("type does not support metadata"),
     }
 }
 
 // Atom functions
 fn deref(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to deref call");
+        return err_arity("deref");
     }
     match *a[0] {
         Atom(ref val) => Ok(val.borrow().clone()),
-        _ => err_str("deref called on non-atom"),
+        _ => err_type("deref called on non-atom"),
     }
 }
 
 fn reset_bang(a: Vec<MalVal>) -> MalRet {
     if a.len() != 2 {
-        return err_str("Wrong arity to map call");
+        return err_arity("reset!");
     }
     match *a[0] {
         Atom(ref val) => {
@@ -464,33 +745,57 @@ fn reset_bang(a: Vec<MalVal>) -> MalRet {
             *val_cell = a[1].clone();
             Ok(a[1].clone())
         },
-        _ => err_str("reset! called on non-atom"),
+        _ => err_type("reset! called on non-atom"),
     }
 }
 
 fn swap_bang(a: Vec<MalVal>) -> MalRet {
     if a.len() < 2 {
-        return err_str("Wrong arity to swap_q call");
+        return err_arity("swap!");
     }
     let f = a[1].clone();
     match *a[0] {
         Atom(ref val) => {
-            let mut val_cell = val.borrow_mut();
+            // f may deref the atom, so don't hold a borrow while it runs
             let mut args = a[2..].to_vec();
-            args.insert(0, val_cell.clone());
-            *val_cell = try!(f.apply(args));
-            Ok(val_cell.clone())
+            args.insert(0, val.borrow().clone());
+            let new_val = f.apply(args)?;
+            *val.borrow_mut() = new_val.clone();
+            Ok(new_val)
         },
-        _ => err_str("swap! called on non-atom"),
+        _ => err_type("swap! called on non-atom"),
     }
 }
 
+// Garbage collection
+fn gc(a: Vec<MalVal>) -> MalRet {
+    if !a.is_empty() {
+        return err_arity("gc");
+    }
+    Ok(_int(gc::collect() as isize))
+}
+
+fn gc_stats(a: Vec<MalVal>) -> MalRet {
+    if !a.is_empty() {
+        return err_arity("gc-stats");
+    }
+    let stats = gc::stats();
+    let mut hm = MalMap::new();
+    hm.insert(types::keyword("envs"), _int(stats.envs as isize));
+    hm.insert(types::keyword("atoms"), _int(stats.atoms as isize));
+    Ok(types::hash_map(hm))
+}
+
 
 pub fn ns() -> HashMap<String,MalVal> {
-    let mut ns = HashMap::new();;
+    let mut ns = HashMap::new();
 
     ns.insert("=".to_string(), func(equal_q));
     ns.insert("throw".to_string(), func(throw));
+    ns.insert("ex-info".to_string(), func(ex_info));
+    ns.insert("ex-data".to_string(), func(ex_data));
+    ns.insert("ex-message".to_string(), func(ex_message));
+    ns.insert("ex-cause".to_string(), func(ex_cause));
     ns.insert("nil?".to_string(), func(types::nil_q));
     ns.insert("true?".to_string(), func(types::true_q));
     ns.insert("false?".to_string(), func(types::false_q));
@@ -517,6 +822,9 @@ pub fn ns() -> HashMap<String,MalVal> {
     ns.insert("*".to_string(), func(mul));
     ns.insert("/".to_string(), func(div));
     ns.insert("time-ms".to_string(), func(time_ms));
+    ns.insert("float?".to_string(), func(types::float_q));
+    ns.insert("int".to_string(), func(int));
+    ns.insert("double".to_string(), func(double));
 
     ns.insert("list".to_string(), func(types::listv));
     ns.insert("list?".to_string(), func(types::list_q));
@@ -531,6 +839,15 @@ pub fn ns() -> HashMap<String,MalVal> {
     ns.insert("keys".to_string(), func(keys));
     ns.insert("vals".to_string(), func(vals));
 
+    ns.insert("set".to_string(), func(set));
+    ns.insert("hash-set".to_string(), func(types::hash_setv));
+    ns.insert("set?".to_string(), func(types::set_q));
+    ns.insert("disj".to_string(), func(disj));
+    ns.insert("union".to_string(), func(union));
+    ns.insert("intersection".to_string(), func(intersection));
+    ns.insert("difference".to_string(), func(difference));
+    ns.insert("subset?".to_string(), func(subset_q));
+
     ns.insert("sequential?".to_string(), func(types::sequential_q));
     ns.insert("cons".to_string(), func(cons));
     ns.insert("concat".to_string(), func(concat));
@@ -553,5 +870,8 @@ pub fn ns() -> HashMap<String,MalVal> {
     ns.insert("reset!".to_string(), func(reset_bang));
     ns.insert("swap!".to_string(), func(swap_bang));
 
-    return ns;
+    ns.insert("gc".to_string(), func(gc));
+    ns.insert("gc-stats".to_string(), func(gc_stats));
+
+    ns
 }
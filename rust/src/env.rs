@@ -2,28 +2,171 @@ use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::HashMap;
 
-use types::{MalVal, MalRet, _nil, list, err_string};
+use types::{MalVal, MalRet, MalError, Symbol, Kind, _nil, list, err_kind, arity_error};
+use gc;
 use types::MalType::{Sym, List, Vector};
 
+// A binding by name. Analyzed code holds on to the var of each global
+// it uses, so a later def! is seen without looking the name up again.
+// None until the name is first defined.
+pub type Var = Rc<RefCell<Option<MalVal>>>;
+
+// The values binding has given dynamic vars, innermost last, by the
+// address of the var. They are kept apart from its value, which def!
+// sets, so a def! in the body of a binding outlasts it.
+thread_local! {
+    static BINDINGS: RefCell<HashMap<*const RefCell<Option<MalVal>>, Vec<MalVal>>> =
+        RefCell::new(HashMap::new());
+}
+
+// Dynamic vars are the globals named like *this*
+pub fn is_dynamic(name: &str) -> bool {
+    name.len() > 2 && name.starts_with('*') && name.ends_with('*')
+}
+
+// The value of var, named name: its innermost binding if it has one
+pub fn var_get(var: &Var, name: &str) -> Option<MalVal> {
+    if is_dynamic(name) {
+        let bound = BINDINGS.with(|b| {
+            b.borrow().get(&Rc::as_ptr(var)).and_then(|vals| vals.last().cloned())
+        });
+        if bound.is_some() {
+            return bound;
+        }
+    }
+    var.borrow().clone()
+}
+
+// Set var, named name: its innermost binding if it has one
+pub fn var_set(var: &Var, name: &str, val: MalVal) {
+    if is_dynamic(name) {
+        let set = BINDINGS.with(|b| match b.borrow_mut().get_mut(&Rc::as_ptr(var)) {
+            Some(vals) => vals.last_mut().map(|v| *v = val.clone()).is_some(),
+            None => false,
+        });
+        if set {
+            return;
+        }
+    }
+    *var.borrow_mut() = Some(val);
+}
+
+// Give var a new binding, until var_unbind
+pub fn var_bind(var: &Var, val: MalVal) {
+    BINDINGS.with(|b| b.borrow_mut().entry(Rc::as_ptr(var)).or_default().push(val));
+}
+
+pub fn var_unbind(var: &Var) {
+    BINDINGS.with(|b| {
+        let mut b = b.borrow_mut();
+        if let Some(vals) = b.get_mut(&Rc::as_ptr(var)) {
+            vals.pop();
+            if vals.is_empty() {
+                b.remove(&Rc::as_ptr(var));
+            }
+        }
+    })
+}
+
 pub struct EnvType {
-    data: HashMap<String,MalVal>,
+    data: HashMap<Symbol,Var>,
+    // the locals of an analyzed fn*, let* or catch* frame (see analyze.rs)
+    slots: Vec<MalVal>,
     outer: Option<Env>,
 }
 
 pub type Env = Rc<RefCell<EnvType>>;
 
 pub fn env_new(outer: Option<Env>) -> Env {
-    Rc::new(RefCell::new(EnvType{data: HashMap::new(), outer: outer}))
+    env_frame(outer, vec![])
+}
+
+pub fn env_frame(outer: Option<Env>, slots: Vec<MalVal>) -> Env {
+    let env = Rc::new(RefCell::new(EnvType{data: HashMap::new(), slots, outer}));
+    gc::track_env(&env);
+    env
 }
 
+// For the cycle collector: the vars, slots and outer env this env refers
+// to, or None if it is borrowed right now
+pub fn env_refs(env: &Env) -> Option<(Vec<Var>, Vec<MalVal>, Option<Env>)> {
+    let e = env.try_borrow().ok()?;
+    Some((e.data.values().cloned().collect(), e.slots.clone(), e.outer.clone()))
+}
+
+// Drop everything the env refers to, breaking any cycle through it
+pub fn env_clear(env: &Env) {
+    let mut e = env.borrow_mut();
+    e.data.clear();
+    e.slots.clear();
+    e.outer = None;
+}
+
+pub fn env_slot(env: &Env, depth: usize, idx: usize) -> MalVal {
+    let e = env.borrow();
+    if depth == 0 {
+        return e.slots[idx].clone();
+    }
+    match e.outer {
+        Some(ref outer) => env_slot(outer, depth - 1, idx),
+        None => _nil(),
+    }
+}
+
+pub fn env_set_slot(env: &Env, idx: usize, val: MalVal) {
+    env.borrow_mut().slots[idx] = val;
+}
+
+// Rebind the slots at idxs to vals for another pass through a loop. This
+// is done in place, unless something besides the caller still refers to
+// env (a closure made in the last pass), in which case a new frame is
+// returned for the loop to carry on in.
+pub fn env_recur(env: &Env, idxs: &[usize], vals: Vec<MalVal>) -> Option<Env> {
+    if Rc::strong_count(env) == 1 {
+        let mut e = env.borrow_mut();
+        for (&i, val) in idxs.iter().zip(vals) {
+            e.slots[i] = val;
+        }
+        return None;
+    }
+    let e = env.borrow();
+    let mut slots = e.slots.clone();
+    for (&i, val) in idxs.iter().zip(vals) {
+        slots[i] = val;
+    }
+    Some(env_frame(e.outer.clone(), slots))
+}
+
+// The var bound to name in this env, created unbound if there isn't one
+pub fn env_var(env: &Env, name: &Symbol) -> Var {
+    env.borrow_mut().data.entry(name.clone())
+        .or_insert_with(|| Rc::new(RefCell::new(None)))
+        .clone()
+}
+
+// The value bound to name in this env itself (not its outer envs)
+pub fn env_lookup(env: &Env, name: &Symbol) -> Option<MalVal> {
+    env.borrow().data.get(name).and_then(|v| var_get(v, name))
+}
+
+// Bind the params of the named function to the args of a call to it
 pub fn env_bind(env: &Env,
                 mbinds: MalVal,
-                mexprs: MalVal) -> Result<Env,String> {
+                mexprs: MalVal,
+                name: Option<&str>) -> Result<Env,MalError> {
     let mut variadic = false;
     match *mbinds {
         List(ref binds,_) | Vector(ref binds,_) => {
             match *mexprs {
                 List(ref exprs,_) | Vector(ref exprs,_) => {
+                    let nfixed = binds.iter().position(|b| match **b {
+                        Sym(ref strn) => *strn == "&",
+                        _ => false,
+                    }).unwrap_or(binds.len());
+                    let has_rest = nfixed < binds.len();
+                    if exprs.len() < nfixed || (exprs.len() > nfixed && !has_rest) {
+                        return Err(arity_error(name, &[(nfixed, has_rest)], exprs.len()));
+                    }
                     let mut it = binds.iter().enumerate();
                     for (i, b) in it.by_ref() {
                         match **b {
@@ -35,25 +178,28 @@ pub fn env_bind(env: &Env,
                                     env_set(env, b.clone(), exprs[i].clone());
                                 }
                             }
-                            _ => return Err("non-symbol bind".to_string()),
+                            _ => return err_kind(Kind::Syntax, "non-symbol bind"),
                         }
                     }
                     if variadic {
-                        let (i, sym) = it.next().unwrap();
+                        let (i, sym) = match it.next() {
+                            Some(isym) => isym,
+                            None => return err_kind(Kind::Syntax, "& bind without a symbol"),
+                        };
                         match **sym {
                             Sym(_) => {
-                                let rest = exprs[i-1..].to_vec();
+                                let rest = exprs.skip(i-1);
                                 env_set(env, sym.clone(), list(rest));
                             }
-                            _ => return Err("& bind to non-symbol".to_string()),
+                            _ => return err_kind(Kind::Syntax, "& bind to non-symbol"),
                         }
                     }
                     Ok(env.clone())
                 },
-                _ => Err("exprs must be a list".to_string()),
+                _ => err_kind(Kind::Type, "exprs must be a list"),
             }
         },
-        _ => Err("binds must be a list".to_string()),
+        _ => err_kind(Kind::Type, "binds must be a list"),
     }
 }
 
@@ -61,7 +207,7 @@ pub fn env_find(env: &Env, key: &MalVal) -> Option<Env> {
     match **key {
         Sym(ref k) => {
             let map = env.borrow();
-            if map.data.contains_key(k) {
+            if map.data.get(k).is_some_and(|v| v.borrow().is_some()) {
                 Some(env.clone())
             } else {
                 match map.outer {
@@ -82,10 +228,7 @@ pub fn env_root(env: &Env) -> Env {
 }
 
 pub fn env_set(env: &Env, key: MalVal, val: MalVal) {
-    match *key {
-        Sym(ref k) => { env.borrow_mut().data.insert(k.to_string(), val); }
-        _ => {},
-    }
+    if let Sym(ref k) = *key { var_set(&env_var(env, k), k, val); }
 }
 
 pub fn env_get(env: &Env, key: &MalVal) -> MalRet {
@@ -93,14 +236,14 @@ pub fn env_get(env: &Env, key: &MalVal) -> MalRet {
         Sym(ref k) => {
             match env_find(env, key) {
                 Some(e) => {
-                    match e.borrow().data.get(k) {
-                        Some(v) => Ok(v.clone()),
+                    match env_lookup(&e, k) {
+                        Some(v) => Ok(v),
                         None => Ok(_nil()),
                     }
                 },
-                None => err_string(format!("'{}' not found", k)),
+                None => err_kind(Kind::NotFound, format!("'{}' not found", k)),
             }
         }
-        _ => err_string("env_get called with non-symbol key".to_string()),
+        _ => err_kind(Kind::Type, "env_get called with non-symbol key"),
     }
 }
@@ -0,0 +1,80 @@
+use types::{MalVal, MalRet, SrcLoc};
+use types::MalType::{Sym, List, MalFunc};
+use env::{Env, env_root};
+use analyze::analyze;
+use compile::{compile, bind_args, Code, Step, Lambda};
+
+pub fn apply_lambda(lambdas: &[Lambda], name: Option<&str>, env: &Env,
+                    args: Vec<MalVal>) -> MalRet {
+    let (lambda, frame) = bind_args(lambdas, name, env, args)?;
+    eval_code(&lambda.body, &frame)
+}
+
+// Whether ast is a (do ...) that eval() should run form by form
+pub fn is_toplevel_do(ast: &MalVal) -> bool {
+    match **ast {
+        List(ref forms,_) if forms.len() > 1 => {
+            matches!(*forms[0], Sym(ref s) if s == "do")
+        },
+        _ => false,
+    }
+}
+
+/// Evaluate ast in the root env of env.
+pub fn eval(ast: MalVal, env: Env) -> MalRet {
+    let root = env_root(&env);
+    // each form is analyzed only once the ones before it have run, so
+    // that macros they define can be used
+    if is_toplevel_do(&ast) {
+        if let List(ref forms,_) = *ast {
+            for form in forms.iter().skip(1).take(forms.len() - 2) {
+                eval(form.clone(), root.clone())?;
+            }
+            return eval(forms[forms.len() - 1].clone(), root);
+        }
+    }
+    eval_code(&compile(analyze(&ast, &root)?), &root)
+}
+
+fn func_name(f: &MalVal) -> Option<String> {
+    match **f {
+        MalFunc(ref mfd,_) => mfd.name.clone(),
+        _ => None,
+    }
+}
+
+// How far compiled code got, so eval_code() can report where an error
+// came from
+pub struct Position {
+    // the innermost read form (one with location metadata) entered
+    pub form: Option<MalVal>,
+    // the mal function whose body is being evaluated (after a tail call)
+    pub func: Option<MalVal>,
+    // the form that first tail called into a mal function
+    pub call: Option<MalVal>,
+}
+
+pub fn eval_code(code: &Code, env: &Env) -> MalRet {
+    let mut pos = Position{form: None, func: None, call: None};
+    let mut step = code(env, &mut pos);
+    loop {
+        match step {
+            Ok(Step::Value(v)) => return Ok(v),
+            Ok(Step::Eval(code, env)) => step = code(&env, &mut pos),
+            // the analyzer only allows recur inside a loop or fn* body
+            Ok(Step::Recur(_)) => unreachable!(),
+            Err(mut e) => {
+                if let Some(loc) = pos.form.as_ref().and_then(SrcLoc::of) {
+                    e = e.at(loc);
+                }
+                if let Some(f) = pos.func {
+                    e = e.in_frame(func_name(&f));
+                    if let Some(loc) = pos.call.as_ref().and_then(SrcLoc::of) {
+                        e = e.at(loc);
+                    }
+                }
+                return Err(e);
+            },
+        }
+    }
+}
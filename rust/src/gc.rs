@@ -0,0 +1,287 @@
+// Cycle collector for environments and atoms.
+//
+// Values are reference counted, so the only garbage Rc can't free is a
+// cycle, and every cycle has to pass through something mutable: an Env
+// (def! a closure into the env it closes over) or an Atom. All envs and
+// atoms are tracked here, and collect() finds the ones that are only
+// reachable from each other (trial deletion, as in CPython's gc):
+//
+//  - starting from the tracked envs and atoms, follow every reference
+//    held in an Rc field (env vars, slots and outer env, var values,
+//    function env/body/params, atom contents, ex-info data and cause,
+//    metadata) or in a collection, and count, per object, how many of its
+//    references were found this way
+//  - anything with more strong references than were found is referenced
+//    from somewhere else (the Rust stack, the embedding program, a native
+//    closure, ...) and is a root; everything reachable from a root is live
+//  - the remaining envs and atoms are garbage: clearing them breaks the
+//    cycles and Rc frees the rest
+//
+// List, vector, hash-map and set elements live in persistent chunks that
+// collections share, so one reference held in a chunk is reached from
+// every collection that shares it. Each is counted once, by the address
+// of the slot it is held in. References from analyzed code, which holds
+// constants and the vars of the globals it uses, are never counted; that
+// only makes objects look more reachable, so nothing live is collected.
+
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use types::{MalVal, MalType, _nil};
+use types::MalType::{List, Vector, Hash_Map, Set, Func, NativeFunc, MalFunc, Atom, ExInfo};
+use env::{Env, EnvType, Var, env_refs, env_clear};
+
+// collect automatically once this many envs and atoms are tracked
+const MIN_THRESHOLD: usize = 10000;
+
+/// Counts of live (not yet freed) tracked objects.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub envs: usize,
+    pub atoms: usize,
+}
+
+struct Heap {
+    envs: Vec<Weak<RefCell<EnvType>>>,
+    atoms: Vec<Weak<MalType>>,
+    threshold: usize,
+    collecting: bool,
+}
+
+impl Heap {
+    fn prune(&mut self) {
+        self.envs.retain(|e| e.strong_count() > 0);
+        self.atoms.retain(|a| a.strong_count() > 0);
+    }
+
+    // Whether enough has been tracked since the last prune to be worth
+    // collecting; resets the threshold if not
+    fn should_collect(&mut self) -> bool {
+        if self.collecting || self.envs.len() + self.atoms.len() < self.threshold {
+            return false;
+        }
+        self.prune();
+        let live = self.envs.len() + self.atoms.len();
+        if live * 2 < self.threshold {
+            self.threshold = MIN_THRESHOLD.max(live * 2);
+            return false;
+        }
+        true
+    }
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const { RefCell::new(Heap {
+        envs: vec![],
+        atoms: vec![],
+        threshold: MIN_THRESHOLD,
+        collecting: false,
+    }) };
+}
+
+pub fn track_env(env: &Env) {
+    let collect_now = HEAP.with(|h| {
+        let mut h = h.borrow_mut();
+        h.envs.push(Rc::downgrade(env));
+        h.should_collect()
+    });
+    if collect_now { collect(); }
+}
+
+pub fn track_atom(atom: &MalVal) {
+    let collect_now = HEAP.with(|h| {
+        let mut h = h.borrow_mut();
+        h.atoms.push(Rc::downgrade(atom));
+        h.should_collect()
+    });
+    if collect_now { collect(); }
+}
+
+/// Live envs and atoms on this thread.
+pub fn stats() -> GcStats {
+    HEAP.with(|h| {
+        let mut h = h.borrow_mut();
+        h.prune();
+        GcStats{envs: h.envs.len(), atoms: h.atoms.len()}
+    })
+}
+
+#[derive(Clone)]
+enum Node {
+    Env(Env),
+    Var(Var),
+    Val(MalVal),
+}
+
+impl Node {
+    fn addr(&self) -> *const u8 {
+        match *self {
+            Node::Env(ref e) => Rc::as_ptr(e) as *const u8,
+            Node::Var(ref v) => Rc::as_ptr(v) as *const u8,
+            Node::Val(ref v) => Rc::as_ptr(v) as *const u8,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            Node::Env(ref e) => Rc::strong_count(e),
+            Node::Var(ref v) => Rc::strong_count(v),
+            Node::Val(ref v) => Rc::strong_count(v),
+        }
+    }
+
+    // What this node references, each with the address of the slot that
+    // holds it if that is in a collection's chunk, or None if the node is
+    // borrowed right now and can't be looked at
+    fn refs(&self) -> Option<Vec<(Node, Option<*const MalVal>)>> {
+        let v = match *self {
+            Node::Env(ref e) => {
+                let (vars, slots, outer) = env_refs(e)?;
+                let mut refs: Vec<Node> = vars.into_iter().map(Node::Var).collect();
+                refs.extend(slots.into_iter().map(Node::Val));
+                refs.extend(outer.map(Node::Env));
+                return Some(fields(refs));
+            },
+            Node::Var(ref var) => {
+                let val = var.try_borrow().ok()?.clone();
+                return Some(fields(val.into_iter().map(Node::Val).collect()));
+            },
+            Node::Val(ref v) => v,
+        };
+        let slot = |v: &MalVal| (Node::Val(v.clone()), Some(v as *const MalVal));
+        let mut refs: Vec<(Node, Option<*const MalVal>)> = match **v {
+            List(ref items,_) | Vector(ref items,_) => items.iter().map(slot).collect(),
+            Hash_Map(ref hm,_) => hm.iter().flat_map(|(k, v)| vec![slot(k), slot(v)]).collect(),
+            Set(ref items,_) => items.iter().map(slot).collect(),
+            _ => vec![],
+        };
+        refs.extend(fields(match **v {
+            List(_,ref meta) | Vector(_,ref meta) | Hash_Map(_,ref meta) | Set(_,ref meta) |
+            Func(_,ref meta) | NativeFunc(_,ref meta) => vec![Node::Val(meta.clone())],
+            MalFunc(ref mfd,ref meta) => vec![Node::Env(mfd.env.clone()),
+                                              Node::Val(mfd.exp.clone()),
+                                              Node::Val(mfd.params.clone()),
+                                              Node::Val(meta.clone())],
+            Atom(ref val) => vec![Node::Val(val.try_borrow().ok()?.clone())],
+            ExInfo(ref e,ref meta) => vec![Node::Val(e.data.clone()), Node::Val(e.cause.clone()),
+                                           Node::Val(meta.clone())],
+            _ => vec![],
+        }));
+        Some(refs)
+    }
+}
+
+// References held in Rc fields, which no other object shares
+fn fields(nodes: Vec<Node>) -> Vec<(Node, Option<*const MalVal>)> {
+    nodes.into_iter().map(|n| (n, None)).collect()
+}
+
+fn holds_refs(node: &Node) -> bool {
+    match *node {
+        Node::Env(_) | Node::Var(_) => true,
+        Node::Val(ref v) => matches!(**v, List(..) | Vector(..) | Hash_Map(..) | Set(..) |
+                                          Func(..) | NativeFunc(..) | MalFunc(..) |
+                                          Atom(_) | ExInfo(..)),
+    }
+}
+
+struct Graph {
+    nodes: Vec<Node>,
+    index: HashMap<*const u8, usize>,
+    edges: Vec<Vec<usize>>,
+    // references to each node found in other nodes
+    found: Vec<usize>,
+    // nodes that couldn't be looked at, and so must be kept
+    pinned: Vec<bool>,
+    // the collection slots whose reference has been counted
+    slots: HashSet<*const MalVal>,
+}
+
+impl Graph {
+    fn node(&mut self, n: Node) -> usize {
+        let addr = n.addr();
+        if let Some(&i) = self.index.get(&addr) {
+            return i;
+        }
+        let i = self.nodes.len();
+        self.nodes.push(n);
+        self.index.insert(addr, i);
+        self.edges.push(vec![]);
+        self.found.push(0);
+        self.pinned.push(false);
+        i
+    }
+}
+
+/// Free every env and atom that is only reachable through a cycle.
+/// Returns how many were cleared.
+pub fn collect() -> usize {
+    let tracked = HEAP.with(|h| {
+        let mut h = h.borrow_mut();
+        h.collecting = true;
+        h.prune();
+        let mut tracked: Vec<Node> = h.envs.iter()
+            .filter_map(|e| e.upgrade()).map(Node::Env).collect();
+        tracked.extend(h.atoms.iter().filter_map(|a| a.upgrade()).map(Node::Val));
+        tracked
+    });
+
+    let mut g = Graph{nodes: vec![], index: HashMap::new(), edges: vec![],
+                      found: vec![], pinned: vec![], slots: HashSet::new()};
+    for n in tracked {
+        g.node(n);
+    }
+    let mut i = 0;
+    while i < g.nodes.len() {
+        match g.nodes[i].refs() {
+            Some(refs) => {
+                for (r, slot) in refs.into_iter().filter(|r| holds_refs(&r.0)) {
+                    let j = g.node(r);
+                    if slot.is_none_or(|slot| g.slots.insert(slot)) {
+                        g.found[j] += 1;
+                    }
+                    g.edges[i].push(j);
+                }
+            },
+            None => g.pinned[i] = true,
+        }
+        i += 1;
+    }
+
+    // g.nodes holds one reference to each node itself
+    let mut live = vec![false; g.nodes.len()];
+    let mut stack: Vec<usize> = (0..g.nodes.len())
+        .filter(|&i| g.pinned[i] || g.nodes[i].strong_count() - 1 > g.found[i])
+        .collect();
+    while let Some(i) = stack.pop() {
+        if live[i] { continue; }
+        live[i] = true;
+        stack.extend(g.edges[i].iter().cloned().filter(|&j| !live[j]));
+    }
+
+    // g.nodes keeps everything alive until the loop is done, so clearing
+    // one object never frees another one mid-sweep
+    let mut cleared = 0;
+    for (i, n) in g.nodes.iter().enumerate() {
+        if live[i] { continue; }
+        match *n {
+            Node::Env(ref e) => env_clear(e),
+            Node::Val(ref v) => match **v {
+                Atom(ref val) => *val.borrow_mut() = _nil(),
+                _ => continue,
+            },
+            Node::Var(_) => continue,
+        }
+        cleared += 1;
+    }
+    drop(g);
+
+    HEAP.with(|h| {
+        let mut h = h.borrow_mut();
+        h.collecting = false;
+        h.prune();
+        h.threshold = MIN_THRESHOLD.max((h.envs.len() + h.atoms.len()) * 2);
+    });
+    cleared
+}
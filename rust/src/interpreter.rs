@@ -0,0 +1,128 @@
+use std::rc::Rc;
+
+use types::{MalVal, MalRet, MalMap, Symbol, err_str, err_type, symbol, strn, list, hash_map, _nil, native_func};
+use types::MalType::{Strn, List, Vector};
+use env::{env_set, env_new, env_lookup, Env};
+use reader;
+use eval::eval;
+use core;
+use ns;
+use gc::{self, GcStats};
+
+// mal-defined prelude, evaluated in order into every new interpreter
+static PRELUDE: &[&str] = &[
+    "(def! *host-language* \"rust\")",
+    "(def! not (fn* (a) (if a false true)))",
+    "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))",
+    "(def! *gensym-counter* (atom 0))",
+    "(def! gensym (fn* [] (symbol (str \"G__\" (swap! *gensym-counter* (fn* [x] (+ 1 x)))))))",
+    "(defmacro! or (fn* (& xs) (if (empty? xs) nil (if (= 1 (count xs)) (first xs) (let* (condvar (gensym)) `(let* (~condvar ~(first xs)) (if ~condvar ~condvar (or ~@(rest xs)))))))))",
+];
+
+/// An embeddable mal interpreter.
+///
+/// Owns a root environment populated with `core::ns()` and the
+/// mal-defined prelude (`not`, `cond`, `or`, ...).
+pub struct Interpreter {
+    env: Env,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        // core.rs: defined using rust
+        let env = env_new(None);
+        for (k, v) in core::ns().into_iter() {
+            env_set(&env, symbol(&k), v);
+        }
+        env_set(&env, symbol("*ARGV*"), list(vec![]));
+        // see ns.rs
+        env_set(&env, symbol("*ns*"), symbol(ns::USER));
+        env_set(&env, symbol("*ns-aliases*"), hash_map(MalMap::new()));
+        env_set(&env, symbol("*loaded-libs*"), hash_map(MalMap::new()));
+        env_set(&env, symbol("*load-path*"), ns::default_load_path());
+        env_set(&env, symbol("*file*"), _nil());
+        // weak, so that the root env doesn't keep itself alive
+        let root = Rc::downgrade(&env);
+        env_set(&env, symbol("load-file"), native_func("load-file", 1, Some(1), move |a| {
+            match (&*a[0], root.upgrade()) {
+                (Strn(path), Some(root)) => ns::load_file(&root, path),
+                (Strn(_), None) => err_str("load-file outlived its interpreter"),
+                _ => err_type("load-file called with non-string"),
+            }
+        }));
+
+        // core.mal: defined using the language itself
+        let mal = Interpreter { env };
+        for form in PRELUDE.iter() {
+            if let Err(e) = mal.eval_str(form) {
+                panic!("prelude form {} failed: {}", form, e);
+            }
+        }
+        mal
+    }
+
+    /// The root environment that all evaluation happens in.
+    pub fn env(&self) -> Env {
+        self.env.clone()
+    }
+
+    /// Read the first form in `src` and evaluate it.
+    pub fn eval_str(&self, src: &str) -> MalRet {
+        let ast = reader::read_str(src.to_string())?;
+        self.eval_value(ast)
+    }
+
+    /// Evaluate an already read form.
+    pub fn eval_value(&self, ast: MalVal) -> MalRet {
+        eval(ast, self.env.clone())
+    }
+
+    /// Evaluate every form in the file at `path`, as `load-file` does.
+    pub fn load_file(&self, path: &str) -> MalRet {
+        ns::load_file(&self.env, path)
+    }
+
+    /// Bind `name` to `val` in the root environment.
+    pub fn define(&self, name: &str, val: MalVal) {
+        env_set(&self.env, symbol(name), val);
+    }
+
+    /// Have `require` look for libs in `dirs` before the directories
+    /// already in `*load-path*` (from `MAL_PATH` by default).
+    pub fn prepend_load_path(&self, dirs: &[String]) {
+        let mut path: Vec<MalVal> = dirs.iter().map(|d| strn(d)).collect();
+        if let Some(old) = env_lookup(&self.env, &Symbol::new("*load-path*")) {
+            if let List(ref old,_) | Vector(ref old,_) = *old {
+                path.extend(old.iter().cloned());
+            }
+        }
+        self.define("*load-path*", list(path));
+    }
+
+    /// Bind `name` to a native closure that accepts at least `min_arity`
+    /// and at most `max_arity` (unbounded if `None`) arguments.
+    pub fn define_fn<F>(&self, name: &str,
+                        min_arity: usize, max_arity: Option<usize>, f: F)
+        where F: Fn(Vec<MalVal>) -> MalRet + 'static
+    {
+        self.define(name, native_func(name, min_arity, max_arity, f));
+    }
+
+    /// Free environments and atoms that are only kept alive by reference
+    /// cycles (recursive closures, self-referencing atoms). Returns how
+    /// many were freed; also runs automatically as they accumulate.
+    pub fn gc(&self) -> usize {
+        gc::collect()
+    }
+
+    /// Live environment and atom counts.
+    pub fn gc_stats(&self) -> GcStats {
+        gc::stats()
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
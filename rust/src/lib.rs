@@ -2,14 +2,23 @@ extern crate libc;
 extern crate regex;
 extern crate time;
 extern crate num;
+extern crate im_rc;
 
 macro_rules! regex {
     ($e:expr) => (::regex::Regex::new($e).unwrap())
 }
 
+pub mod analyze;
+pub mod compile;
 pub mod core;
 pub mod env;
+pub mod eval;
+pub mod gc;
+pub mod interpreter;
+pub mod ns;
 pub mod printer;
 pub mod reader;
 pub mod readline;
 pub mod types;
+
+pub use interpreter::Interpreter;
@@ -0,0 +1,208 @@
+// Namespaces for the stepA evaluator, and loading files into them.
+//
+// All vars still live in the root env. A name def!'d in a namespace other
+// than user is qualified with it (my.util/f), and the analyzer resolves
+// the symbols of code read in that namespace to match (see resolve()).
+// The state this needs is kept in root vars, where mal code can look at
+// it:
+//
+//   *ns*           the symbol of the current namespace
+//   *ns-aliases*   a map from each namespace to its aliases, which map an
+//                  alias to the namespace it stands for
+//   *loaded-libs*  a map from each lib loaded by require to its file
+//   *load-path*    the directories that require looks for libs in
+//   *file*         the file being loaded, nil at the REPL
+
+use std::env as stdenv;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use types::{MalVal, MalRet, MalError, MalMap, Symbol, Kind, err_kind, err_syntax};
+use types::{symbol, strn, string, list, hash_map, _nil};
+use types::MalType::{Sym, Keyword, Strn, List, Vector, Hash_Map};
+use env::{Env, env_lookup, env_set};
+use reader;
+use eval::eval;
+
+pub const USER: &str = "user";
+
+// The initial *load-path*: the directories in MAL_PATH, or the current
+// directory if there are none
+pub fn default_load_path() -> MalVal {
+    let dirs: Vec<MalVal> = match stdenv::var_os("MAL_PATH") {
+        Some(path) => stdenv::split_paths(&path)
+            .filter(|d| !d.as_os_str().is_empty())
+            .map(|d| string(d.to_string_lossy().into_owned()))
+            .collect(),
+        None => vec![],
+    };
+    if dirs.is_empty() {
+        list(vec![strn(".")])
+    } else {
+        list(dirs)
+    }
+}
+
+fn root_var(root: &Env, name: &str) -> MalVal {
+    env_lookup(root, &Symbol::new(name)).unwrap_or_else(_nil)
+}
+
+fn map_of(m: &MalVal) -> MalMap {
+    match **m {
+        Hash_Map(ref hm,_) => hm.clone(),
+        _ => MalMap::new(),
+    }
+}
+
+// The current namespace, or None for user
+pub fn current(root: &Env) -> Option<Symbol> {
+    match *root_var(root, "*ns*") {
+        Sym(ref ns) if *ns != USER => Some(ns.clone()),
+        _ => None,
+    }
+}
+
+// name def!'d in namespace ns
+pub fn qualify(ns: Option<&Symbol>, name: &Symbol) -> Symbol {
+    match ns {
+        Some(ns) if !name.contains('/') => Symbol::new(&format!("{}/{}", ns, name)),
+        _ => name.clone(),
+    }
+}
+
+// The var name that a/b stands for, given the aliases of the namespace
+fn resolve_qualified(aliases: Option<&MalMap>, name: &Symbol) -> Option<Symbol> {
+    let i = name.find('/')?;
+    if i == 0 || i == name.len() - 1 {
+        return None;
+    }
+    let (prefix, rest) = (&name[..i], &name[i+1..]);
+    let target = match aliases.and_then(|a| a.get(&symbol(prefix))).map(|t| &**t) {
+        Some(Sym(ref target)) => target.to_string(),
+        _ => prefix.to_string(),
+    };
+    if target == USER {
+        Some(Symbol::new(rest))
+    } else {
+        Some(Symbol::new(&format!("{}/{}", target, rest)))
+    }
+}
+
+// The name of the var that name refers to in namespace ns. Besides its
+// own vars, a namespace sees the unqualified ones of core and user, unless
+// it has defined the same name.
+pub fn resolve(root: &Env, ns: Option<&Symbol>, name: &Symbol) -> Symbol {
+    let ns_sym = symbol(ns.map_or(USER, |ns| &**ns));
+    let aliases = map_of(&root_var(root, "*ns-aliases*")).get(&ns_sym).map(map_of);
+    if let Some(var) = resolve_qualified(aliases.as_ref(), name) {
+        return var;
+    }
+    let var = qualify(ns, name);
+    if var != *name && env_lookup(root, &var).is_none() && env_lookup(root, name).is_some() {
+        return name.clone();
+    }
+    var
+}
+
+fn find_lib(root: &Env, lib: &Symbol) -> Result<String, MalError> {
+    let file = format!("{}.mal", lib.replace('.', "/"));
+    if let List(ref dirs,_) | Vector(ref dirs,_) = *root_var(root, "*load-path*") {
+        for dir in dirs.iter() {
+            if let Strn(ref dir) = **dir {
+                let path = Path::new(dir).join(&file);
+                if path.is_file() {
+                    return Ok(path.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    err_kind(Kind::Io, format!("require: can't find {} in *load-path*", file))
+}
+
+// Read and evaluate the forms of the file at path one at a time, in
+// namespace ns. *ns* and *file* are restored afterwards. Returns the value
+// of the last form.
+fn load(root: &Env, path: &str, ns: MalVal) -> MalRet {
+    let mut src = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+        return err_kind(Kind::Io, e.to_string());
+    }
+    let (ns_was, file_was) = (root_var(root, "*ns*"), root_var(root, "*file*"));
+    env_set(root, symbol("*ns*"), ns);
+    env_set(root, symbol("*file*"), strn(path));
+    let mut forms = reader::FormReader::new(src, strn(path));
+    let mut res = Ok(_nil());
+    while let Some(form) = forms.next_form() {
+        res = form.and_then(|form| eval(form, root.clone()));
+        if res.is_err() {
+            break;
+        }
+    }
+    env_set(root, symbol("*ns*"), ns_was);
+    env_set(root, symbol("*file*"), file_was);
+    res
+}
+
+// (load-file path): load the file at path, relative to the directory of
+// the file being loaded if there is one, in the current namespace
+pub fn load_file(root: &Env, path: &str) -> MalRet {
+    let path = match *root_var(root, "*file*") {
+        Strn(ref file) if Path::new(path).is_relative() => match Path::new(file).parent() {
+            Some(dir) => dir.join(path).to_string_lossy().into_owned(),
+            None => path.to_string(),
+        },
+        _ => path.to_string(),
+    };
+    load(root, &path, root_var(root, "*ns*"))
+}
+
+// Load lib unless it already has been. It is marked as loaded before its
+// file is, so that requires that go round in a cycle end.
+fn load_lib(root: &Env, lib: &Symbol) -> MalRet {
+    let lib_sym = symbol(lib);
+    let loaded = map_of(&root_var(root, "*loaded-libs*"));
+    if loaded.contains_key(&lib_sym) {
+        return Ok(_nil());
+    }
+    let path = find_lib(root, lib)?;
+    env_set(root, symbol("*loaded-libs*"), hash_map(loaded.update(lib_sym.clone(), strn(&path))));
+    if let Err(e) = load(root, &path, symbol(USER)) {
+        let loaded = map_of(&root_var(root, "*loaded-libs*")).without(&lib_sym);
+        env_set(root, symbol("*loaded-libs*"), hash_map(loaded));
+        return Err(e);
+    }
+    Ok(_nil())
+}
+
+fn is_as(v: &MalVal) -> bool {
+    matches!(**v, Keyword(ref k) if &**k == "as")
+}
+
+fn bad_spec(spec: &MalVal) -> MalRet {
+    err_syntax(format!("require: bad spec {}", spec.pr_str(true)))
+}
+
+// (require spec): load the lib named by spec, which is lib, [lib] or
+// [lib :as alias], and make alias stand for it in the current namespace
+pub fn require(root: &Env, spec: &MalVal) -> MalRet {
+    let (lib, alias) = match **spec {
+        Sym(ref lib) => (lib.clone(), None),
+        List(ref v,_) | Vector(ref v,_) => match (v.len(), v.front().map(|l| &**l)) {
+            (1, Some(Sym(ref lib))) => (lib.clone(), None),
+            (3, Some(Sym(ref lib))) if is_as(&v[1]) && matches!(*v[2], Sym(_)) => {
+                (lib.clone(), Some(v[2].clone()))
+            },
+            _ => return bad_spec(spec),
+        },
+        _ => return bad_spec(spec),
+    };
+    load_lib(root, &lib)?;
+    if let Some(alias) = alias {
+        let ns = root_var(root, "*ns*");
+        let all = map_of(&root_var(root, "*ns-aliases*"));
+        let mine = all.get(&ns).map(map_of).unwrap_or_default().update(alias, symbol(&lib));
+        env_set(root, symbol("*ns-aliases*"), hash_map(all.update(ns, hash_map(mine))));
+    }
+    Ok(_nil())
+}
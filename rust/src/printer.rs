@@ -4,7 +4,7 @@ pub fn escape_str(s: &str) -> String {
     let mut escaped = String::new();
     escaped.push('"');
     for c in s.chars() {
-        let _ = match c {
+        match c {
           '"' => escaped.push_str("\\\""),
           '\\' => escaped.push_str("\\\\"),
           '\x08' => escaped.push_str("\\b"),
@@ -25,15 +25,17 @@ pub fn unescape_str(s: &str) -> String {
     let re1 = regex!(r#"\\""#);
     let re2 = regex!(r#"\\n"#);
     let re3 = regex!(r#"\\\\"#);
-    re3.replace_all(&re2.replace_all(&re1.replace_all(&s, "\""), "\n"), "\\")
+    re3.replace_all(&re2.replace_all(&re1.replace_all(s, "\""), "\n"), "\\")
 }
 
-pub fn pr_list(lst: &Vec<MalVal>, pr: bool,
-               start: &str , end: &str, join: &str) -> String {
+pub fn pr_list<'a, I>(lst: I, pr: bool,
+                      start: &str , end: &str, join: &str) -> String
+    where I: IntoIterator<Item=&'a MalVal>
+{
     let mut first = true;
     let mut res = String::new();
     res.push_str(start);
-    for mv in lst.iter() {
+    for mv in lst {
         if first {
             first = false;
         } else {
@@ -1,42 +1,64 @@
 use std::borrow::ToOwned;
 use types::MalError::{ErrString, ErrMalVal};
-use types::{MalVal, MalRet,
-            _nil, _true, _false, _int, symbol, string, list, vector, hash_mapv,
-            err_str, err_string, err_val};
+use types::MalType::{Hash_Map, Strn};
+use types::{MalVal, MalRet, SrcLoc,
+            _nil, _true, _false, _int, _bigint, _float, symbol, keyword, string,
+            list, listm, vectorm, hash_mapv, hash_mapm, setm, err_syntax, err_val};
 use super::printer::unescape_str;
 
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    col: usize,
+}
+
 #[derive(Debug, Clone)]
 struct Reader {
-    tokens: Vec<String>,
+    tokens: Vec<Token>,
     position: usize,
+    file: MalVal,
 }
 
 impl Reader {
     fn next(&mut self) -> Option<String> {
         if self.position < self.tokens.len() {
             self.position += 1;
-            Some(self.tokens[self.position-1].to_string())
+            Some(self.tokens[self.position-1].text.to_string())
         } else {
             None
         }
     }
     fn peek(&self) -> Option<String> {
         if self.position < self.tokens.len() {
-            Some(self.tokens[self.position].to_string())
+            Some(self.tokens[self.position].text.to_string())
         } else {
             None
         }
     }
+    // location metadata for the next token
+    fn loc(&self) -> MalVal {
+        match self.tokens.get(self.position) {
+            Some(t) => SrcLoc::meta(&self.file, t.line, t.col),
+            None => _nil(),
+        }
+    }
 }
 
-fn tokenize(str: String) -> Vec<String> {
+fn tokenize(str: String) -> Vec<Token> {
     let mut results = vec![];
-    let re = regex!(r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"|;.*|[^\s\[\]{}('"`,;)]*)"###);
+    let re = regex!(r###"[\s,]*(~@|#\{|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"|;.*|[^\s\[\]{}('"`,;)]*)"###);
+    let (mut line, mut col, mut offset) = (1, 1, 0);
     for cap in re.captures_iter(&str) {
         let group = cap.at(1).unwrap_or("");
-        if group == "" { break; }
+        if group.is_empty() { break; }
+        let start = cap.pos(1).unwrap().0;
+        for c in str[offset..start].chars() {
+            if c == '\n' { line += 1; col = 1; } else { col += 1; }
+        }
+        offset = start;
         if group.starts_with(";") { continue; }
-        results.push(group.to_owned());
+        results.push(Token{text: group.to_owned(), line, col});
     }
     results
 }
@@ -44,17 +66,33 @@ fn tokenize(str: String) -> Vec<String> {
 fn read_atom(rdr : &mut Reader) -> MalRet {
     let otoken = rdr.next();
     //println!("read_atom: {}", otoken);
-    if otoken.is_none() { return err_str("read_atom underflow"); }
+    if otoken.is_none() { return err_syntax("read_atom underflow"); }
     let stoken = otoken.unwrap();
     let token = &stoken[..];
     if regex!(r"^-?[0-9]+$").is_match(token) {
-        let num : Option<isize> = token.parse().ok();
-        Ok(_int(num.unwrap()))
+        match token.parse() {
+            Ok(num) => Ok(_int(num)),
+            Err(_) => match token.parse() {
+                Ok(big) => Ok(_bigint(big)),
+                Err(_) => err_syntax(format!("invalid integer literal: {}", token)),
+            },
+        }
+    } else if regex!(r"^-?[0-9]+(\.[0-9]*)?([eE][-+]?[0-9]+)?$").is_match(token) {
+        match token.parse() {
+            Ok(num) => Ok(_float(num)),
+            Err(_) => err_syntax(format!("invalid float literal: {}", token)),
+        }
+    } else if token == "##Inf" {
+        Ok(_float(f64::INFINITY))
+    } else if token == "##-Inf" {
+        Ok(_float(f64::NEG_INFINITY))
+    } else if token == "##NaN" {
+        Ok(_float(f64::NAN))
     } else if regex!(r#"^".*"$"#).is_match(token) {
         let new_str = &token[1..token.len()-1];
         Ok(string(unescape_str(new_str)))
     } else if regex!(r#"^:"#).is_match(token) {
-        Ok(string(format!("\u{29e}{}", &token[1..])))
+        Ok(keyword(&token[1..]))
     } else if token == "nil" {
         Ok(_nil())
     } else if token == "true" {
@@ -89,8 +127,9 @@ fn read_seq(rdr : &mut Reader, start: &str, end: &str) -> Result<Vec<MalVal>,Str
 
         match read_form(rdr) {
             Ok(mv) => ast_vec.push(mv),
-            Err(ErrString(es)) => return Err(es),
+            Err(ErrString(_, es)) => return Err(es),
             Err(ErrMalVal(_)) => return Err("read_seq exception".to_string()),
+            Err(e) => return Err(e.to_string()),
         }
     }
     rdr.next();
@@ -99,29 +138,45 @@ fn read_seq(rdr : &mut Reader, start: &str, end: &str) -> Result<Vec<MalVal>,Str
 }
 
 fn read_list(rdr : &mut Reader) -> MalRet {
+    let loc = rdr.loc();
     match read_seq(rdr, "(", ")") {
-        Ok(seq) => Ok(list(seq)),
-        Err(es) => err_string(es),
+        Ok(seq) => Ok(listm(seq, loc)),
+        Err(es) => err_syntax(es),
     }
 }
 
 fn read_vector(rdr : &mut Reader) -> MalRet {
+    let loc = rdr.loc();
     match read_seq(rdr, "[", "]") {
-        Ok(seq) => Ok(vector(seq)),
-        Err(es) => err_string(es),
+        Ok(seq) => Ok(vectorm(seq, loc)),
+        Err(es) => err_syntax(es),
     }
 }
 
 fn read_hash_map(rdr : &mut Reader) -> MalRet {
+    let loc = rdr.loc();
     match read_seq(rdr, "{", "}") {
-        Ok(seq) => hash_mapv(seq),
-        Err(es) => err_string(es),
+        Ok(ref seq) if seq.len() % 2 == 1 => err_syntax("odd number of forms in map literal"),
+        Ok(seq) => match *hash_mapv(seq)? {
+            Hash_Map(ref hm,_) => Ok(hash_mapm(hm.clone(), loc)),
+            _ => err_syntax("read_hash_map: expected hash-map"),
+        },
+        Err(es) => err_syntax(es),
+    }
+}
+
+fn read_set(rdr : &mut Reader) -> MalRet {
+    let loc = rdr.loc();
+    match read_seq(rdr, "#{", "}") {
+        Ok(seq) => Ok(setm(seq.into_iter().collect(), loc)),
+        Err(es) => err_syntax(es),
     }
 }
 
 fn read_form(rdr : &mut Reader) -> MalRet {
     let otoken = rdr.peek();
     //println!("read_form: {}", otoken);
+    if otoken.is_none() { return err_syntax("read_form underflow"); }
     let stoken = otoken.unwrap();
     let token = &stoken[..];
     match token {
@@ -173,26 +228,59 @@ fn read_form(rdr : &mut Reader) -> MalRet {
             }
         },
 
-        ")" => err_str("unexected ')'"),
+        ")" => err_syntax("unexected ')'"),
         "(" => read_list(rdr),
 
-        "]" => err_str("unexected ']'"),
+        "]" => err_syntax("unexected ']'"),
         "[" => read_vector(rdr),
 
-        "}" => err_str("unexected '}'"),
+        "}" => err_syntax("unexected '}'"),
         "{" => read_hash_map(rdr),
+        "#{" => read_set(rdr),
 
         _   => read_atom(rdr)
     }
 }
 
+/// The forms of a text, read one at a time, so that each can be evaluated
+/// before the next is read.
+pub struct FormReader {
+    rdr: Reader,
+}
+
+impl FormReader {
+    pub fn new(str: String, file: MalVal) -> FormReader {
+        FormReader{rdr: Reader{tokens: tokenize(str), position: 0, file}}
+    }
+
+    /// The next form, or None at the end of the text. A syntax error
+    /// reports where the form it is in starts.
+    pub fn next_form(&mut self) -> Option<MalRet> {
+        let (line, col) = match self.rdr.tokens.get(self.rdr.position) {
+            Some(t) => (t.line, t.col),
+            None => return None,
+        };
+        let file = match *self.rdr.file {
+            Strn(ref f) => Some(f.clone()),
+            _ => None,
+        };
+        Some(read_form(&mut self.rdr).map_err(|e| e.at(SrcLoc{file, line, col})))
+    }
+}
+
 pub fn read_str(str :String) -> MalRet {
+    read_str_from(str, _nil())
+}
+
+// Read the first form of str, recording file (a string, or nil if the
+// text did not come from a file) in the location of every collection
+pub fn read_str_from(str :String, file: MalVal) -> MalRet {
     let tokens = tokenize(str);
-    if tokens.len() == 0 {
+    if tokens.is_empty() {
         // any malval as the error slot means empty line
         return err_val(_nil())
     }
     //println!("tokens: {}", tokens);
-    let rdr = &mut Reader{tokens: tokens, position: 0};
+    let rdr = &mut Reader{tokens, position: 0, file};
     read_form(rdr)
 }
@@ -1,11 +1,19 @@
 #![allow(dead_code)]
 
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::fmt;
+use num::BigInt;
+use im_rc;
+use num::traits::ToPrimitive;
 use super::printer::{escape_str,pr_list};
 use super::env::{Env,env_new,env_bind};
+use super::compile::Lambda;
+use super::eval::apply_lambda;
+use super::gc;
 
 use self::MalType::*;
 use self::MalError::*;
@@ -17,39 +25,392 @@ pub enum MalType {
     True,
     False,
     Int(isize),
+    Float(f64),
+    // only holds values outside the isize range, see _bigint
+    Big(BigInt),
     Strn(String),
-    Sym(String),
-    List(Vec<MalVal>, MalVal),
-    Vector(Vec<MalVal>, MalVal),
-    Hash_Map(HashMap<String, MalVal>, MalVal),
+    Sym(Symbol),
+    Keyword(Rc<str>),
+    List(MalSeq, MalVal),
+    Vector(MalSeq, MalVal),
+    Hash_Map(MalMap, MalVal),
+    Set(MalSet, MalVal),
     Func(fn(Vec<MalVal>) -> MalRet, MalVal),
+    NativeFunc(NativeFuncData, MalVal),
     MalFunc(MalFuncData, MalVal),
     Atom(RefCell<MalVal>),
+    ExInfo(ExInfoData, MalVal),
 }
 
 pub type MalVal = Rc<MalType>;
 
-#[derive(Debug)]
+// Persistent collections: clones share structure, so rest, cons, conj
+// and assoc don't copy the whole collection
+pub type MalSeq = im_rc::Vector<MalVal>;
+pub type MalMap = im_rc::HashMap<MalVal, MalVal>;
+pub type MalSet = im_rc::HashSet<MalVal>;
+
+// The kind of a native error. A catch* clause selects native errors of a
+// kind by its :mal/... keyword, and the ex-data of a caught one holds it
+// under :kind.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Error,
+    Arity,
+    Type,
+    NotFound,
+    Syntax,
+    Range,
+    Arithmetic,
+    Io,
+}
+
+impl Kind {
+    pub fn keyword(self) -> MalVal {
+        keyword(match self {
+            Kind::Error => "mal/error",
+            Kind::Arity => "mal/arity",
+            Kind::Type => "mal/type",
+            Kind::NotFound => "mal/not-found",
+            Kind::Syntax => "mal/syntax",
+            Kind::Range => "mal/range",
+            Kind::Arithmetic => "mal/arithmetic",
+            Kind::Io => "mal/io",
+        })
+    }
+}
+
+// The message strings catch* has bound native errors to, by address, with
+// their kinds. catch* binds the message itself, which prints the way
+// native errors always have, and ex-message, ex-data and throw look it up
+// here to treat it as the error it stands for. An entry's Weak keeps its
+// address from being reused, and entries are dropped once their string
+// has been freed.
+struct Caught {
+    errors: HashMap<*const MalType, (Weak<MalType>, Kind)>,
+    threshold: usize,
+}
+
+thread_local! {
+    static CAUGHT: RefCell<Caught> = RefCell::new(Caught{errors: HashMap::new(), threshold: 64});
+}
+
+fn remember_caught(v: &MalVal, kind: Kind) {
+    CAUGHT.with(|c| {
+        let mut c = c.borrow_mut();
+        if c.errors.len() >= c.threshold {
+            c.errors.retain(|_, e| e.0.strong_count() > 0);
+            c.threshold = 64.max(c.errors.len() * 2);
+        }
+        c.errors.insert(Rc::as_ptr(v), (Rc::downgrade(v), kind));
+    })
+}
+
+// The kind of the native error that catch* bound v to, if it was one
+pub fn caught_kind(v: &MalVal) -> Option<Kind> {
+    CAUGHT.with(|c| c.borrow().errors.get(&Rc::as_ptr(v)).map(|e| e.1))
+}
+
+// The ex-info that the native error catch* bound v to stands for
+pub fn caught_ex_info(v: &MalVal) -> Option<ExInfoData> {
+    let kind = caught_kind(v)?;
+    let mut data = MalMap::new();
+    data.insert(keyword("kind"), kind.keyword());
+    match **v {
+        Strn(ref s) => Some(ExInfoData{message: s.clone(), data: hash_map(data), cause: _nil()}),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum MalError {
-    ErrString(String),
+    // a native error, and its message
+    ErrString(Kind, String),
     ErrMalVal(MalVal),
+    // an error annotated with where it was raised and the mal
+    // functions it has unwound through
+    ErrTrace(Box<MalError>, Box<Backtrace>),
 }
 
 pub type MalRet = Result<MalVal,MalError>;
 
+// One mal function call that an error unwound through
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name: Option<String>,
+    pub loc:  Option<SrcLoc>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Backtrace {
+    // where the error was raised
+    pub loc:    Option<SrcLoc>,
+    // innermost call first
+    pub frames: Vec<Frame>,
+    // position reached in the frame currently being unwound
+    pos:        Option<SrcLoc>,
+}
+
+impl MalError {
+    // The value a catch* clause binds for this error: the message of a
+    // native error
+    pub fn exc_value(self) -> MalVal {
+        match self {
+            ErrString(kind, s) => {
+                let v = string(s);
+                remember_caught(&v, kind);
+                v
+            },
+            ErrMalVal(mv) => mv,
+            ErrTrace(e, _) => e.exc_value(),
+        }
+    }
+
+    // The kind keyword of a native error, or the :kind of a thrown ex-info
+    pub fn kind(&self) -> Option<MalVal> {
+        match *self {
+            ErrString(kind, _) => Some(kind.keyword()),
+            ErrMalVal(ref mv) => match **mv {
+                ExInfo(ref e,_) => e.kind(),
+                _ => None,
+            },
+            ErrTrace(ref e, _) => e.kind(),
+        }
+    }
+
+    pub fn loc(&self) -> Option<&SrcLoc> {
+        self.backtrace().and_then(|bt| bt.loc.as_ref())
+    }
+
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        match *self {
+            ErrTrace(_, ref bt) => Some(bt),
+            _ => None,
+        }
+    }
+
+    fn split(self) -> (MalError, Backtrace) {
+        match self {
+            ErrTrace(e, bt) => (*e, *bt),
+            e => (e, Backtrace::default()),
+        }
+    }
 
-pub fn err_string(s: String) -> MalRet {
-    Err(ErrString(s))
+    // Record that the error passed through the form at loc. Only the
+    // innermost form of each frame is kept.
+    pub fn at(self, loc: SrcLoc) -> MalError {
+        let (e, mut bt) = self.split();
+        if bt.pos.is_none() {
+            if bt.loc.is_none() { bt.loc = Some(loc.clone()); }
+            bt.pos = Some(loc);
+        }
+        ErrTrace(Box::new(e), Box::new(bt))
+    }
+
+    // Record that the error unwound out of a call to the named function
+    pub fn in_frame(self, name: Option<String>) -> MalError {
+        let (e, mut bt) = self.split();
+        let loc = bt.pos.take();
+        bt.frames.push(Frame{name, loc});
+        ErrTrace(Box::new(e), Box::new(bt))
+    }
+}
+
+impl fmt::Display for MalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrString(_, ref s) => write!(f, "{}", s),
+            ErrMalVal(ref mv) => write!(f, "{}", mv.pr_str(true)),
+            ErrTrace(ref e, ref bt) => match bt.loc {
+                Some(ref loc) => write!(f, "{} (at {})", e, loc),
+                None => write!(f, "{}", e),
+            },
+        }
+    }
+}
+
+impl Frame {
+    pub fn to_mal(&self) -> MalVal {
+        let mut hm = MalMap::new();
+        let name = match self.name {
+            Some(ref name) => strn(name),
+            None => _nil(),
+        };
+        hm.insert(keyword("fn"), name);
+        if let Some(ref loc) = self.loc {
+            if let Some(ref file) = loc.file {
+                hm.insert(keyword("file"), strn(file));
+            }
+            hm.insert(keyword("line"), _int(loc.line as isize));
+            hm.insert(keyword("column"), _int(loc.col as isize));
+        }
+        hash_map(hm)
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.name.as_ref().map_or("fn*", |n| &n[..]);
+        match self.loc {
+            Some(ref loc) => write!(f, "{} ({})", name, loc),
+            None => write!(f, "{}", name),
+        }
+    }
 }
 
-pub fn err_str(s: &str) -> MalRet {
-    Err(ErrString(s.to_string()))
+impl Backtrace {
+    // The frames as a mal vector of {:fn :file :line :column} maps
+    pub fn to_mal(&self) -> MalVal {
+        vector(self.frames.iter().map(|fr| fr.to_mal()).collect::<MalSeq>())
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for frame in self.frames.iter() {
+            writeln!(f, "  at {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn err_string<T>(s: String) -> Result<T,MalError> {
+    Err(ErrString(Kind::Error, s))
+}
+
+pub fn err_str<T>(s: &str) -> Result<T,MalError> {
+    Err(ErrString(Kind::Error, s.to_string()))
+}
+
+pub fn err_kind<T, S: Into<String>>(kind: Kind, s: S) -> Result<T,MalError> {
+    Err(ErrString(kind, s.into()))
+}
+
+pub fn err_type<T, S: Into<String>>(s: S) -> Result<T,MalError> {
+    err_kind(Kind::Type, s)
+}
+
+pub fn err_syntax<T, S: Into<String>>(s: S) -> Result<T,MalError> {
+    err_kind(Kind::Syntax, s)
+}
+
+pub fn err_arity<T>(name: &str) -> Result<T,MalError> {
+    err_kind(Kind::Arity, format!("Wrong arity to {} call", name))
+}
+
+// The error for a call with nargs args to the named function, which takes
+// arities: each the number of its fixed params and whether it has a rest
+// param
+pub fn arity_error(name: Option<&str>, arities: &[(usize, bool)], nargs: usize) -> MalError {
+    let mut fixed: Vec<usize> = arities.iter().filter(|a| !a.1).map(|a| a.0).collect();
+    fixed.sort_unstable();
+    let mut expected: Vec<String> = fixed.iter().map(|n| n.to_string()).collect();
+    expected.extend(arities.iter().filter(|a| a.1).map(|a| format!("at least {}", a.0)));
+    let expected = match expected.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        _ => expected.concat(),
+    };
+    ErrString(Kind::Arity, format!("wrong number of arguments to {}: expected {}, got {}",
+                      name.unwrap_or("fn*"), expected, nargs))
 }
 
 pub fn err_val(mv: MalVal) -> MalRet {
     Err(ErrMalVal(mv))
 }
 
+// Where a form was read from, as recorded by the reader in the
+// form's metadata ({:file "..." :line 1 :column 1})
+#[derive(Debug, Clone, PartialEq)]
+pub struct SrcLoc {
+    pub file: Option<String>,
+    pub line: usize,
+    pub col:  usize,
+}
+
+impl SrcLoc {
+    pub fn of(form: &MalVal) -> Option<SrcLoc> {
+        let meta = match **form {
+            List(_,ref meta) | Vector(_,ref meta) | Hash_Map(_,ref meta) => meta,
+            _ => return None,
+        };
+        let hm = match **meta {
+            Hash_Map(ref hm,_) => hm,
+            _ => return None,
+        };
+        let num = |k: &str| match hm.get(&keyword(k)).map(|v| &**v) {
+            Some(&Int(n)) if n > 0 => Some(n as usize),
+            _ => None,
+        };
+        let file = match hm.get(&keyword("file")).map(|v| &**v) {
+            Some(Strn(f)) => Some(f.clone()),
+            _ => None,
+        };
+        Some(SrcLoc{file,
+                    line: num("line")?,
+                    col: num("column")?})
+    }
+
+    pub fn meta(file: &MalVal, line: usize, col: usize) -> MalVal {
+        let mut hm = MalMap::new();
+        if let Strn(_) = **file {
+            hm.insert(keyword("file"), file.clone());
+        }
+        hm.insert(keyword("line"), _int(line as isize));
+        hm.insert(keyword("column"), _int(col as isize));
+        hash_map(hm)
+    }
+}
+
+impl fmt::Display for SrcLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}:{}:{}", file, self.line, self.col),
+            None => write!(f, "line {}, column {}", self.line, self.col),
+        }
+    }
+}
+
+// A native function that may capture host state
+#[derive(Clone)]
+pub struct NativeFuncData {
+    pub name:      String,
+    pub min_arity: usize,
+    pub max_arity: Option<usize>,
+    pub f:         Rc<dyn Fn(Vec<MalVal>) -> MalRet>,
+}
+
+impl NativeFuncData {
+    pub fn call(&self, args: Vec<MalVal>) -> MalRet {
+        let too_many = match self.max_arity {
+            Some(max) => args.len() > max,
+            None => false,
+        };
+        if args.len() < self.min_arity || too_many {
+            return err_arity(&self.name);
+        }
+        (self.f)(args)
+    }
+}
+
+// A structured error value, made by ex-info
+#[derive(Clone)]
+pub struct ExInfoData {
+    pub message: String,
+    // a map, which may hold a keyword under :kind for catch* to select on
+    pub data:    MalVal,
+    // the error this one was raised in response to, or nil
+    pub cause:   MalVal,
+}
+
+impl ExInfoData {
+    pub fn kind(&self) -> Option<MalVal> {
+        match *self.data {
+            Hash_Map(ref hm,_) => hm.get(&keyword("kind")).cloned(),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct MalFuncData {
     pub eval:     fn(MalVal, Env) -> MalRet,
@@ -57,6 +418,10 @@ pub struct MalFuncData {
     pub env:      Env,
     pub params:   MalVal,
     pub is_macro: bool,
+    pub name:     Option<String>,
+    // the analyzed arities, for functions created by the stepA evaluator.
+    // Those keep their whole fn* form in exp, and nil in params.
+    pub code:     Option<Rc<[Lambda]>>,
 }
 
 impl MalType {
@@ -67,11 +432,21 @@ impl MalType {
             True => "true".to_string(),
             False => "false".to_string(),
             Int(v) => v.to_string(),
-            Sym(ref v) => v.clone(),
+            Big(ref v) => v.to_string(),
+            Float(v) => {
+                if v.is_nan() {
+                    "##NaN".to_string()
+                } else if v.is_infinite() {
+                    if v > 0.0 { "##Inf" } else { "##-Inf" }.to_string()
+                } else {
+                    // Debug keeps the ".0" on integral values
+                    format!("{:?}", v)
+                }
+            },
+            Sym(ref v) => v.to_string(),
+            Keyword(ref k) => format!(":{}", k),
             Strn(ref v) => {
-                if v.starts_with("\u{29e}") {
-                    format!(":{}", &v[2..])
-                } else if print_readably {
+                if print_readably {
                     escape_str(v)
                 } else {
                     v.clone()
@@ -85,43 +460,78 @@ impl MalType {
             },
             Hash_Map(ref v,_) => {
                 let mut res = String::new();
-                res.push_str("{");
+                res.push('{');
                 for (i, (key, value)) in v.iter().enumerate() {
-                    if i != 0 { res.push_str(" "); }
-                    if key.starts_with("\u{29e}") {
-                        res.push_str(":");
-                        res.push_str(&key[2..])
-                    } else if print_readably {
-                        res.push_str(&escape_str(key))
-                    } else {
-                        res.push_str(key)
-                    }
-                    res.push_str(" ");
+                    if i != 0 { res.push(' '); }
+                    res.push_str(&key.pr_str(_r));
+                    res.push(' ');
                     res.push_str(&value.pr_str(_r));
                 }
-                res.push_str("}");
+                res.push('}');
                 res
             },
+            Set(ref v,_) => {
+                pr_list(v, _r, "#{", "}", " ")
+            },
             // TODO: better native function representation
-            Func(_, _) => format!("#<function ...>"),
+            Func(_, _) => "#<function ...>".to_string(),
+            NativeFunc(ref nf,_) => format!("#<function {}>", nf.name),
+            MalFunc(ref mf,_) if mf.code.is_some() => format!("{:?}", mf.exp),
             MalFunc(ref mf,_) => format!("(fn* {:?} {:?})", mf.params, mf.exp),
             Atom(ref v) => format!("(atom {:?})", &**v.borrow()),
+            ExInfo(ref e,_) => {
+                let mut res = format!("#error {{:message {} :data {}",
+                                      Strn(e.message.clone()).pr_str(true), e.data.pr_str(true));
+                if !matches!(*e.cause, Nil) {
+                    res.push_str(&format!(" :cause {}", e.cause.pr_str(true)));
+                }
+                res.push('}');
+                res
+            },
+        }
+    }
+
+    // The keyword name of the type of a value, as catch* selectors use
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Nil => "nil",
+            True | False => "boolean",
+            Int(_) | Float(_) | Big(_) => "number",
+            Strn(_) => "string",
+            Sym(_) => "symbol",
+            Keyword(_) => "keyword",
+            List(..) => "list",
+            Vector(..) => "vector",
+            Hash_Map(..) => "map",
+            Set(..) => "set",
+            Func(..) | NativeFunc(..) | MalFunc(..) => "fn",
+            Atom(_) => "atom",
+            ExInfo(..) => "ex-info",
         }
     }
 
     pub fn apply(&self, args:Vec<MalVal>) -> MalRet {
         match *self {
             Func(f,_) => f(args),
+            NativeFunc(ref nf,_) => nf.call(args),
+            MalFunc(MalFuncData{code: Some(ref code), ref env, ref name, ..},_) => {
+                apply_lambda(code, name.as_deref(), env, args)
+                    .map_err(|e| e.in_frame(name.clone()))
+            },
             MalFunc(ref mf,_) => {
                 let mfc = mf.clone();
                 let alst = list(args);
                 let new_env = env_new(Some(mfc.env.clone()));
-                match env_bind(&new_env, mfc.params, alst) {
-                    Ok(_) => (mfc.eval)(mfc.exp, new_env),
-                    Err(e) => err_string(e),
+                match env_bind(&new_env, mfc.params, alst, mfc.name.as_deref()) {
+                    Ok(_) => {
+                        let name = mfc.name;
+                        (mfc.eval)(mfc.exp, new_env)
+                            .map_err(|e| e.in_frame(name))
+                    },
+                    Err(e) => Err(e),
                 }
             },
-            _ => err_str("attempt to call non-function"),
+            _ => err_type("attempt to call non-function"),
         }
 
     }
@@ -133,18 +543,86 @@ impl PartialEq for MalType {
             (&Nil, &Nil) |
             (&True, &True) |
             (&False, &False) => true,
-            (&Int(ref a), &Int(ref b)) => a == b,
-            (&Strn(ref a), &Strn(ref b)) => a == b,
-            (&Sym(ref a), &Sym(ref b)) => a == b,
+            (Int(a), Int(b)) => a == b,
+            (Float(a), Float(b)) => a == b,
+            (Big(a), Big(b)) => a == b,
+            (Strn(a), Strn(b)) => a == b,
+            (Sym(a), Sym(b)) => a == b,
+            (Keyword(a), Keyword(b)) => Rc::ptr_eq(a, b),
             (&List(ref a,_), &List(ref b,_)) |
             (&Vector(ref a,_), &Vector(ref b,_)) |
             (&List(ref a,_), &Vector(ref b,_)) |
             (&Vector(ref a,_), &List(ref b,_)) => a == b,
-            (&Hash_Map(ref a,_), &Hash_Map(ref b,_)) => a == b,
-            // TODO: fix this
-            (&Func(_,_), &Func(_,_)) => false,
-            (&MalFunc(_,_), &MalFunc(_,_)) => false,
-            _ => return false,
+            (Hash_Map(a,_), Hash_Map(b,_)) => a == b,
+            (Set(a,_), Set(b,_)) => a == b,
+            // the copy def! makes to name a function shares its code and
+            // env, and is still the same function
+            (MalFunc(a,_), MalFunc(b,_)) => match (&a.code, &b.code) {
+                (Some(ca), Some(cb)) => {
+                    Rc::ptr_eq(ca, cb) && Rc::ptr_eq(&a.env, &b.env) && a.is_macro == b.is_macro
+                },
+                _ => ::std::ptr::eq(self, other),
+            },
+            // only equal to themselves
+            (Func(..), Func(..)) |
+            (NativeFunc(..), NativeFunc(..)) |
+            (Atom(_), Atom(_)) |
+            (ExInfo(..), ExInfo(..)) => ::std::ptr::eq(self, other),
+            _ => false,
+        }
+    }
+}
+
+// Functions, atoms and ex-infos are only equal to themselves (or, for a
+// mal function, to the named copy def! makes of it), so they only hash
+// their variant; that also keeps an atom's hash stable across reset!. As
+// map keys and set members they are found again by identity.
+// NaN is not equal to anything, itself included, so a NaN key is never
+// found again once inserted.
+impl Eq for MalType {}
+
+// A hash of items that doesn't depend on the order they come in, for the
+// contents of maps and sets
+fn unordered_hash<I: Iterator>(items: I) -> u64 where I::Item: Hash {
+    let mut sum: u64 = 0;
+    for item in items {
+        let mut h = DefaultHasher::new();
+        item.hash(&mut h);
+        sum = sum.wrapping_add(h.finish());
+    }
+    sum
+}
+
+impl Hash for MalType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Nil => 0u8.hash(state),
+            True => 1u8.hash(state),
+            False => 2u8.hash(state),
+            Int(n) => { 3u8.hash(state); n.hash(state) },
+            Strn(ref s) => { 4u8.hash(state); s.hash(state) },
+            Sym(ref s) => { 5u8.hash(state); s.hash(state) },
+            // lists and vectors are equal to each other
+            List(ref v,_) | Vector(ref v,_) => { 6u8.hash(state); v.hash(state) },
+            Hash_Map(ref hm,_) => {
+                7u8.hash(state);
+                hm.len().hash(state);
+                unordered_hash(hm.iter()).hash(state)
+            },
+            Func(_,_) | NativeFunc(_,_) | MalFunc(_,_) | ExInfo(..) => 8u8.hash(state),
+            Atom(_) => 9u8.hash(state),
+            Keyword(ref k) => { 10u8.hash(state); k.hash(state) },
+            Float(f) => {
+                11u8.hash(state);
+                // 0.0 == -0.0
+                if f == 0.0 { 0u64.hash(state) } else { f.to_bits().hash(state) }
+            },
+            Big(ref b) => { 12u8.hash(state); b.hash(state) },
+            Set(ref s,_) => {
+                13u8.hash(state);
+                s.len().hash(state);
+                unordered_hash(s.iter()).hash(state)
+            },
         }
     }
 }
@@ -160,7 +638,7 @@ impl fmt::Debug for MalType {
 pub fn _nil() -> MalVal { Rc::new(Nil) }
 pub fn nil_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to nil? call");
+        return err_arity("nil?");
     }
     match *a[0].clone() {
         Nil => Ok(_true()),
@@ -171,7 +649,7 @@ pub fn nil_q(a:Vec<MalVal>) -> MalRet {
 pub fn _true() -> MalVal { Rc::new(True) }
 pub fn true_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to true? call");
+        return err_arity("true?");
     }
     match *a[0].clone() {
         True => Ok(_true()),
@@ -182,7 +660,7 @@ pub fn true_q(a:Vec<MalVal>) -> MalRet {
 pub fn _false() -> MalVal { Rc::new(False) }
 pub fn false_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to false? call");
+        return err_arity("false?");
     }
     match *a[0].clone() {
         False => Ok(_true()),
@@ -192,39 +670,123 @@ pub fn false_q(a:Vec<MalVal>) -> MalRet {
 
 pub fn string_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to string? call");
+        return err_arity("string?");
     }
-    match *a[0].clone() {
-        Strn(ref s) => {
-            if s.starts_with("\u{29e}") {
-                Ok(_false())
-            } else {
-                Ok(_true())
-            }
-        },
-        _ => Ok(_false()),
+    match *a[0] {
+        Strn(_) => Ok(_true()),
+        _       => Ok(_false()),
     }
 }
 
 pub fn _int(i: isize) -> MalVal { Rc::new(Int(i)) }
 
+// Demotes to Int when the value fits, so each integer has exactly one
+// representation
+pub fn _bigint(b: BigInt) -> MalVal {
+    match b.to_isize() {
+        Some(i) => _int(i),
+        None => Rc::new(Big(b)),
+    }
+}
+
+pub fn _float(f: f64) -> MalVal { Rc::new(Float(f)) }
+pub fn float_q(a:Vec<MalVal>) -> MalRet {
+    if a.len() != 1 {
+        return err_arity("float?");
+    }
+    match *a[0] {
+        Float(_) => Ok(_true()),
+        _        => Ok(_false()),
+    }
+}
+
 
 // Symbols
-pub fn symbol(strn: &str) -> MalVal { Rc::new(Sym(strn.to_string())) }
+
+/// An interned symbol name. There is a single Symbol per name, so
+/// symbols compare and hash by pointer; they deref to the name.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+impl Symbol {
+    pub fn new(name: &str) -> Symbol {
+        SYMBOLS.with(|syms| {
+            if let Some(sym) = syms.borrow().get(name) {
+                return sym.clone();
+            }
+            let sym = Symbol(Rc::from(name));
+            syms.borrow_mut().insert(name.to_string(), sym.clone());
+            sym
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.0) as *const u8 as usize).hash(state)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        *self.0 == *other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Symbol {
+    fn eq(&self, other: &&'a str) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl ::std::ops::Deref for Symbol {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", &*self.0)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", &*self.0)
+    }
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<HashMap<String, Symbol>> = RefCell::new(HashMap::new());
+}
+
+pub fn symbol(strn: &str) -> MalVal { Rc::new(Sym(Symbol::new(strn))) }
 pub fn _symbol(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to symbol call");
+        return err_arity("symbol");
     }
     match *a[0].clone() {
-        Strn(ref s) => {
-            Ok(Rc::new(Sym(s.to_string())))
-        },
-        _ => return err_str("symbol called on non-string"),
+        Strn(ref s) => Ok(symbol(s)),
+        _ => err_type("symbol called on non-string"),
     }
 }
 pub fn symbol_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to symbol? call");
+        return err_arity("symbol?");
     }
     match *a[0].clone() {
         Sym(_) => Ok(_true()),
@@ -232,29 +794,39 @@ pub fn symbol_q(a:Vec<MalVal>) -> MalRet {
     }
 }
 
-// Keywords
+// Keywords are interned: there is a single Keyword value per name, so
+// comparing two keywords is a pointer check
+thread_local! {
+    static KEYWORDS: RefCell<HashMap<String, MalVal>> = RefCell::new(HashMap::new());
+}
+
+pub fn keyword(name: &str) -> MalVal {
+    KEYWORDS.with(|kws| {
+        if let Some(kw) = kws.borrow().get(name) {
+            return kw.clone();
+        }
+        let kw = Rc::new(Keyword(Rc::from(name)));
+        kws.borrow_mut().insert(name.to_string(), kw.clone());
+        kw
+    })
+}
 pub fn _keyword(a: Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to keyword call");
+        return err_arity("keyword");
     }
     match *a[0] {
-        Strn(ref s) => Ok(Rc::new(Strn(format!("\u{29e}{}", s)))),
-        _ => err_str("keyword called on non-string"),
+        Strn(ref s) => Ok(keyword(s)),
+        Keyword(_) => Ok(a[0].clone()),
+        _ => err_type("keyword called on non-string"),
     }
 }
 pub fn keyword_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to keyword? call");
+        return err_arity("keyword?");
     }
-    match *a[0].clone() {
-        Strn(ref s) => {
-            if s.starts_with("\u{29e}") {
-                Ok(_true())
-            } else {
-                Ok(_false())
-            }
-        },
-        _ => Ok(_false()),
+    match *a[0] {
+        Keyword(_) => Ok(_true()),
+        _          => Ok(_false()),
     }
 }
 
@@ -264,14 +836,14 @@ pub fn strn(strn: &str) -> MalVal { Rc::new(Strn(strn.to_string())) }
 pub fn string(strn: String) -> MalVal { Rc::new(Strn(strn)) }
 
 // Lists
-pub fn list(seq: Vec<MalVal>) -> MalVal { Rc::new(List(seq,_nil())) }
-pub fn listm(seq: Vec<MalVal>, meta: MalVal) -> MalVal {
-    Rc::new(List(seq,meta))
+pub fn list<S: Into<MalSeq>>(seq: S) -> MalVal { Rc::new(List(seq.into(),_nil())) }
+pub fn listm<S: Into<MalSeq>>(seq: S, meta: MalVal) -> MalVal {
+    Rc::new(List(seq.into(),meta))
 }
 pub fn listv(seq:Vec<MalVal>) -> MalRet { Ok(list(seq)) }
 pub fn list_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to list? call");
+        return err_arity("list?");
     }
     match *a[0].clone() {
         List(_,_) => Ok(_true()),
@@ -280,14 +852,14 @@ pub fn list_q(a:Vec<MalVal>) -> MalRet {
 }
 
 // Vectors
-pub fn vector(seq: Vec<MalVal>) -> MalVal { Rc::new(Vector(seq,_nil())) }
-pub fn vectorm(seq: Vec<MalVal>, meta: MalVal) -> MalVal {
-    Rc::new(Vector(seq,meta))
+pub fn vector<S: Into<MalSeq>>(seq: S) -> MalVal { Rc::new(Vector(seq.into(),_nil())) }
+pub fn vectorm<S: Into<MalSeq>>(seq: S, meta: MalVal) -> MalVal {
+    Rc::new(Vector(seq.into(),meta))
 }
 pub fn vectorv(seq: Vec<MalVal>) -> MalRet { Ok(vector(seq)) }
 pub fn vector_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to vector? call");
+        return err_arity("vector?");
     }
     match *a[0].clone() {
         Vector(_,_) => Ok(_true()),
@@ -296,53 +868,36 @@ pub fn vector_q(a:Vec<MalVal>) -> MalRet {
 }
 
 // Hash Maps
-pub fn hash_map(hm: HashMap<String,MalVal>) -> MalVal {
+pub fn hash_map(hm: MalMap) -> MalVal {
     Rc::new(Hash_Map(hm,_nil()))
 }
-pub fn hash_mapm(hm: HashMap<String,MalVal>, meta: MalVal) -> MalVal {
+pub fn hash_mapm(hm: MalMap, meta: MalVal) -> MalVal {
     Rc::new(Hash_Map(hm,meta))
 }
-pub fn _assoc(hm: &HashMap<String,MalVal>, a:Vec<MalVal>) -> MalRet {
+pub fn _assoc(hm: &MalMap, a:Vec<MalVal>) -> MalRet {
     if a.len() % 2 == 1 {
-        return err_str("odd number of hash-map keys/values");
+        return err_kind(Kind::Arity, "odd number of hash-map keys/values");
     }
     let mut new_hm = hm.clone();
-    let mut it = a.iter();
-    loop {
-        let k = match it.next() {
-            Some(mv) => match *mv.clone() {
-                Strn(ref s) => s.to_string(),
-                _ => return err_str("key is not a string in hash-map call"),
-            },
-            None => break,
-        };
-        let v = it.next().unwrap();
-        new_hm.insert(k, v.clone());
+    for kv in a.chunks(2) {
+        new_hm.insert(kv[0].clone(), kv[1].clone());
     }
     Ok(Rc::new(Hash_Map(new_hm,_nil())))
 }
-pub fn _dissoc(hm: &HashMap<String,MalVal>, a:Vec<MalVal>) -> MalRet {
+pub fn _dissoc(hm: &MalMap, a:Vec<MalVal>) -> MalRet {
     let mut new_hm = hm.clone();
-    let mut it = a.iter();
-    loop {
-        let k = match it.next() {
-            Some(mv) => match *mv.clone() {
-                Strn(ref s) => s.to_string(),
-                _ => return err_str("key is not a string in hash-map call"),
-            },
-            None => break,
-        };
-        new_hm.remove(&k);
+    for k in a.iter() {
+        new_hm.remove(k);
     }
     Ok(Rc::new(Hash_Map(new_hm,_nil())))
 }
 pub fn hash_mapv(seq: Vec<MalVal>) -> MalRet {
-    let new_hm: HashMap<String,MalVal> = HashMap::new();
+    let new_hm = MalMap::new();
     _assoc(&new_hm, seq)
 }
 pub fn hash_map_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to map? call");
+        return err_arity("map?");
     }
     match *a[0].clone() {
         Hash_Map(_,_) => Ok(_true()),
@@ -350,6 +905,26 @@ pub fn hash_map_q(a:Vec<MalVal>) -> MalRet {
     }
 }
 
+// Sets
+pub fn set(s: MalSet) -> MalVal {
+    Rc::new(Set(s,_nil()))
+}
+pub fn setm(s: MalSet, meta: MalVal) -> MalVal {
+    Rc::new(Set(s,meta))
+}
+pub fn hash_setv(seq: Vec<MalVal>) -> MalRet {
+    Ok(set(seq.into_iter().collect()))
+}
+pub fn set_q(a:Vec<MalVal>) -> MalRet {
+    if a.len() != 1 {
+        return err_arity("set?");
+    }
+    match *a[0] {
+        Set(_,_) => Ok(_true()),
+        _        => Ok(_false()),
+    }
+}
+
 // Functions
 pub fn func(f: fn(Vec<MalVal>) -> MalRet) -> MalVal {
     Rc::new(Func(f, _nil()))
@@ -357,26 +932,51 @@ pub fn func(f: fn(Vec<MalVal>) -> MalRet) -> MalVal {
 pub fn funcm(f: fn(Vec<MalVal>) -> MalRet, meta: MalVal) -> MalVal {
     Rc::new(Func(f, meta))
 }
+pub fn native_func<F>(name: &str,
+                      min_arity: usize,
+                      max_arity: Option<usize>,
+                      f: F) -> MalVal
+    where F: Fn(Vec<MalVal>) -> MalRet + 'static
+{
+    Rc::new(NativeFunc(NativeFuncData{name: name.to_string(),
+                                      min_arity,
+                                      max_arity,
+                                      f: Rc::new(f)},
+                       _nil()))
+}
+pub fn native_funcd(nfd: NativeFuncData, meta: MalVal) -> MalVal {
+    Rc::new(NativeFunc(nfd, meta))
+}
 pub fn malfunc(eval: fn(MalVal, Env) -> MalRet,
                exp: MalVal,
                env: Env,
                params: MalVal,
                meta: MalVal) -> MalVal {
-    Rc::new(MalFunc(MalFuncData{eval: eval,
-                                exp: exp,
-                                env: env,
-                                params: params,
-                                is_macro: false},meta))
+    Rc::new(MalFunc(MalFuncData{eval,
+                                exp,
+                                env,
+                                params,
+                                is_macro: false,
+                                name: None,
+                                code: None},meta))
 }
 pub fn malfuncd(mfd: MalFuncData, meta: MalVal) -> MalVal {
     Rc::new(MalFunc(mfd,meta))
 }
 
+// Errors
+pub fn ex_info(message: String, data: MalVal, cause: MalVal) -> MalVal {
+    Rc::new(ExInfo(ExInfoData{message, data, cause}, _nil()))
+}
+pub fn ex_infom(e: ExInfoData, meta: MalVal) -> MalVal {
+    Rc::new(ExInfo(e, meta))
+}
+
 
 // Atoms
 pub fn atom_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to atom? call");
+        return err_arity("atom?");
     }
     match *a[0].clone() {
         Atom(_) => Ok(_true()),
@@ -385,16 +985,18 @@ pub fn atom_q(a:Vec<MalVal>) -> MalRet {
 }
 pub fn atom(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to atom call");
+        return err_arity("atom");
     }
-    Ok(Rc::new(Atom(RefCell::new(a[0].clone()))))
+    let atom = Rc::new(Atom(RefCell::new(a[0].clone())));
+    gc::track_atom(&atom);
+    Ok(atom)
 }
 
 
 // General functions
 pub fn sequential_q(a:Vec<MalVal>) -> MalRet {
     if a.len() != 1 {
-        return err_str("Wrong arity to sequential? call");
+        return err_arity("sequential?");
     }
     match *a[0].clone() {
         List(_,_) | Vector(_,_) => Ok(_true()),
@@ -0,0 +1,128 @@
+// The embedding API, used the way a host program would
+extern crate mal;
+
+use std::rc::Rc;
+use std::cell::Cell;
+
+use mal::Interpreter;
+use mal::types::{MalVal, _int, symbol, list};
+use mal::types::MalType::{Int, Strn, Keyword, Vector};
+
+fn int(v: &MalVal) -> isize {
+    match **v {
+        Int(n) => n,
+        _ => panic!("expected an integer, got {}", v.pr_str(true)),
+    }
+}
+
+#[test]
+fn define_then_eval_str() {
+    let mal = Interpreter::new();
+    mal.define("answer", _int(41));
+    assert_eq!(int(&mal.eval_str("(+ answer 1)").unwrap()), 42);
+}
+
+#[test]
+fn eval_value() {
+    let mal = Interpreter::new();
+    let form = list(vec![symbol("*"), _int(6), _int(7)]);
+    assert_eq!(int(&mal.eval_value(form).unwrap()), 42);
+}
+
+#[test]
+fn inspect_returned_value() {
+    let mal = Interpreter::new();
+    let v = mal.eval_str("[1 :a \"s\"]").unwrap();
+    match *v {
+        Vector(ref items,_) => {
+            assert_eq!(items.len(), 3);
+            assert_eq!(int(&items[0]), 1);
+            assert!(matches!(*items[1], Keyword(ref k) if &**k == "a"));
+            assert!(matches!(*items[2], Strn(ref s) if s == "s"));
+        },
+        _ => panic!("expected a vector, got {}", v.pr_str(true)),
+    }
+}
+
+#[test]
+fn eval_str_error() {
+    let mal = Interpreter::new();
+    let e = mal.eval_str("(nosuch 1)").unwrap_err();
+    assert!(e.to_string().contains("'nosuch' not found"), "{}", e);
+}
+
+#[test]
+fn load_file() {
+    let mal = Interpreter::new();
+    // the value of the last form, (def! inner-val 7)
+    assert_eq!(int(&mal.load_file("tests/load/inner.mal").unwrap()), 7);
+    assert_eq!(int(&mal.eval_str("inner-val").unwrap()), 7);
+    assert_eq!(mal.eval_str("inner-file").unwrap().pr_str(false), "tests/load/inner.mal");
+    assert!(mal.load_file("tests/load/missing.mal").is_err());
+}
+
+// A native closure that counts its calls and adds its args
+fn counting_add(mal: &Interpreter) -> Rc<Cell<usize>> {
+    let calls = Rc::new(Cell::new(0));
+    let seen = calls.clone();
+    mal.define_fn("counting-add", 1, None, move |args| {
+        seen.set(seen.get() + 1);
+        Ok(_int(args.iter().map(int).sum()))
+    });
+    calls
+}
+
+#[test]
+fn native_closure_through_apply() {
+    let mal = Interpreter::new();
+    let calls = counting_add(&mal);
+    assert_eq!(int(&mal.eval_str("(apply counting-add 1 [2 3])").unwrap()), 6);
+    assert_eq!(calls.get(), 1);
+}
+
+#[test]
+fn native_closure_through_map() {
+    let mal = Interpreter::new();
+    let calls = counting_add(&mal);
+    let v = mal.eval_str("(map counting-add [1 2 3])").unwrap();
+    assert_eq!(v.pr_str(true), "(1 2 3)");
+    assert_eq!(calls.get(), 3);
+}
+
+#[test]
+fn native_closure_through_swap() {
+    let mal = Interpreter::new();
+    let calls = counting_add(&mal);
+    mal.eval_str("(def! a (atom 1))").unwrap();
+    mal.eval_str("(swap! a counting-add 10)").unwrap();
+    mal.eval_str("(swap! a counting-add 100)").unwrap();
+    assert_eq!(int(&mal.eval_str("@a").unwrap()), 111);
+    assert_eq!(calls.get(), 2);
+}
+
+#[test]
+fn native_closure_metadata() {
+    let mal = Interpreter::new();
+    let calls = counting_add(&mal);
+    mal.eval_str("(def! f (with-meta counting-add {:doc \"adds\"}))").unwrap();
+    assert_eq!(mal.eval_str("(meta f)").unwrap().pr_str(true), "{:doc \"adds\"}");
+    assert_eq!(mal.eval_str("(meta counting-add)").unwrap().pr_str(true), "nil");
+    // the copy with metadata still calls the same closure
+    assert_eq!(int(&mal.eval_str("(f 2 3)").unwrap()), 5);
+    assert_eq!(calls.get(), 1);
+}
+
+#[test]
+fn gc_keeps_value_held_by_host() {
+    let mal = Interpreter::new();
+    // an atom in a cycle through two vectors that share their elements,
+    // which only the host holds once it is no longer def!'d
+    let a = mal.eval_str("(def! a (atom nil))").unwrap();
+    // long enough to be kept in a chunk rather than inline
+    let fill = vec![":x"; 100].join(" ");
+    mal.eval_str(&format!("(reset! a (let* [v [a {}]] [v (with-meta v {{}})]))", fill)).unwrap();
+    mal.eval_str("(def! a nil)").unwrap();
+    assert_eq!(mal.gc(), 0);
+    mal.define("a", a);
+    assert_eq!(mal.eval_str("(nth (first @a) 1)").unwrap().pr_str(true), ":x");
+}